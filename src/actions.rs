@@ -0,0 +1,692 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+use crate::arcface::Recognition;
+use crate::distance::Distance;
+use crate::eval::{evaluate, evaluate_folds, evaluate_profile, variant, Evaluation, Result};
+use crate::identification::{Identification, Split};
+use crate::lda::{fit_folds, Lda};
+use crate::pca::Pca;
+use crate::pq::ProductQuantizer;
+use crate::profile::{DType, ReductionProfile};
+use crate::projection::{Projection, RandomProjection};
+use crate::templates::Fusion;
+use crate::{is_complete, Dataset, AMOUNT_FOLDS};
+use itertools::Itertools;
+use kdam::tqdm;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use std::{collections::HashMap, fs::File, io::Write};
+
+/// Maximum rank of the CMC curves written by `identification`
+const CMC_RANKS: usize = 20;
+
+/// Amounts of sub-vectors, i.e. bytes per template, evaluated by `product_quantization`
+const PQ_SUBSPACES: [usize; 10] = [1, 2, 4, 8, 16, 32, 64, 128, 256, 512];
+
+pub fn truncate_embeddings(data: Box<dyn Dataset>, rec: &mut Recognition, eval: &Evaluation) {
+    println!("embedding_dimensions;{}", eval.variants_header());
+    for i in (1..513).rev() {
+        let results = eval
+            .variants()
+            .into_iter()
+            .map(|normalized| {
+                evaluate(data.as_ref(), rec, eval, |emb1, emb2| {
+                    let emb1 = variant(emb1[..i].to_vec(), normalized);
+                    let emb2 = variant(emb2[..i].to_vec(), normalized);
+                    eval.metric.calc(&emb1, &emb2)
+                })
+                .unwrap()
+                .calc()
+            })
+            .join(";");
+
+        println!("{i};{results}");
+    }
+}
+
+/// Identifies an embedding by its exact values, used to cache expensive transformations.
+fn key(emb: &[f32]) -> Vec<u32> {
+    emb.iter().map(|x| x.to_bits()).collect()
+}
+
+/// Same sweep as `truncate_embeddings`, but on the embeddings transformed by `project` to `dims`
+/// dimensions. The dimensions of the transformed embeddings have to be sorted by importance, so
+/// that truncating them keeps the most important ones. Embeddings truncated to `i` dimensions
+/// are multiplied with `scale(i)`.
+fn projection_sweep<P: Fn(&[f32]) -> Vec<f32>, S: Fn(usize) -> f32>(
+    data: Box<dyn Dataset>,
+    rec: &mut Recognition,
+    eval: &Evaluation,
+    dims: usize,
+    project: P,
+    scale: S,
+) {
+    // Projecting is expensive, so every embedding is only projected once
+    let mut projected: HashMap<Vec<u32>, Vec<f32>> = HashMap::new();
+    println!("embedding_dimensions;{}", eval.variants_header());
+    for i in (1..dims + 1).rev() {
+        let results = eval
+            .variants()
+            .into_iter()
+            .map(|normalized| {
+                evaluate(data.as_ref(), rec, eval, |emb1, emb2| {
+                    let mut reduce = |emb: &[f32]| {
+                        let emb = projected.entry(key(emb)).or_insert_with(|| project(emb));
+                        variant(emb[..i].iter().map(|x| x * scale(i)).collect(), normalized)
+                    };
+                    let emb1 = reduce(emb1);
+                    let emb2 = reduce(emb2);
+                    eval.metric.calc(&emb1, &emb2)
+                })
+                .unwrap()
+                .calc()
+            })
+            .join(";");
+
+        println!("{i};{results}");
+    }
+}
+
+/// Same as `truncate_embeddings`, but keeps the first principal components of `pca` instead of
+/// the first dimensions.
+pub fn pca(data: Box<dyn Dataset>, rec: &mut Recognition, eval: &Evaluation, pca: &Pca) {
+    let dims = pca.components.len();
+    projection_sweep(data, rec, eval, dims, |emb| pca.project(emb, dims), |_| 1.)
+}
+
+/// Same as `truncate_embeddings`, but keeps the first discriminant components of `lda` instead
+/// of the first dimensions. Only as many dimensions as `lda` has components are evaluated.
+pub fn lda(data: Box<dyn Dataset>, rec: &mut Recognition, eval: &Evaluation, lda: &Lda) {
+    let dims = lda.components.len();
+    projection_sweep(data, rec, eval, dims, |emb| lda.project(emb, dims), |_| 1.)
+}
+
+/// Same as `lda`, but cross-validated on the folds of `data`: the components used for the pairs
+/// of each fold are fitted on all other folds, leaving out the identities of the fold (see
+/// `lda::fit_folds`). Only as many dimensions as the fold with the fewest components has are
+/// evaluated.
+///
+/// # Panics
+/// - Panics if less than two identities are left to fit on for a fold
+pub fn lda_folds(data: Box<dyn Dataset>, rec: &mut Recognition, eval: &Evaluation) {
+    let folds = data.folds(rec);
+    let ldas: Vec<Lda> = (0..AMOUNT_FOLDS)
+        .map(|fold| Lda::fit(&fit_folds(data.as_ref(), rec, fold)))
+        .collect();
+    let dims = ldas
+        .iter()
+        .map(|lda| lda.components.len())
+        .min()
+        .unwrap_or(0);
+
+    // Projecting is expensive, so every embedding is only projected once per fold
+    let mut projected: Vec<HashMap<Vec<u32>, Vec<f32>>> = vec![HashMap::new(); AMOUNT_FOLDS];
+    println!("embedding_dimensions;{}", eval.variants_header());
+    for i in (1..dims + 1).rev() {
+        let results = eval
+            .variants()
+            .into_iter()
+            .map(|normalized| {
+                evaluate_folds(&folds, eval, |fold, emb1, emb2| {
+                    let mut reduce = |emb: &[f32]| {
+                        let emb = projected[fold]
+                            .entry(key(emb))
+                            .or_insert_with(|| ldas[fold].project(emb, dims));
+                        variant(emb[..i].to_vec(), normalized)
+                    };
+                    let emb1 = reduce(emb1);
+                    let emb2 = reduce(emb2);
+                    eval.metric.calc(&emb1, &emb2)
+                })
+                .calc()
+            })
+            .join(";");
+
+        println!("{i};{results}");
+    }
+}
+
+/// Same as `truncate_embeddings`, but projects the embeddings using a seeded random projection
+/// instead of truncating them. Projections to `i` dimensions are scaled by `1/sqrt(i)`.
+pub fn random_projection(
+    data: Box<dyn Dataset>,
+    rec: &mut Recognition,
+    eval: &Evaluation,
+    projection: Projection,
+    seed: u64,
+) {
+    let matrix = RandomProjection::new(projection, 512, 512, seed);
+    projection_sweep(
+        data,
+        rec,
+        eval,
+        matrix.rows.len(),
+        |emb| matrix.project(emb, matrix.rows.len()),
+        RandomProjection::scale,
+    )
+}
+
+pub fn truncate_embeddings_rel(data: Box<dyn Dataset>, rec: &mut Recognition, eval: &Evaluation) {
+    println!("embedding_dimensions;{}", eval.header());
+    for i in (1..513).rev() {
+        let result = evaluate(data.as_ref(), rec, eval, |emb1, emb2| {
+            eval.metric.calc(&emb1[..i], &emb2[..i])
+        })
+        .unwrap();
+
+        println!("{i};{}", result.calc_rel());
+    }
+}
+
+/// Calculates the ROC curve for every embedding size. Prints AUC and EER per size to stdout and
+/// writes all curves to `roc-<dataset>.csv` and `roc-<dataset>.json`.
+pub fn roc(data: Box<dyn Dataset>, rec: &mut Recognition, eval: &Evaluation) {
+    println!(
+        "embedding_dimensions;auc;eer;eer_threshold{}",
+        eval.tar_header()
+    );
+    let mut csv = String::from("embedding_dimensions;threshold;tpr;fpr\n");
+    let mut json = Vec::new();
+    for i in (1..513).rev() {
+        let result = evaluate(data.as_ref(), rec, eval, |emb1, emb2| {
+            eval.metric.calc(&emb1[..i], &emb2[..i])
+        })
+        .unwrap();
+        let roc = result.merged(None).roc();
+
+        println!(
+            "{i};{};{};{}{}",
+            roc.auc,
+            roc.eer,
+            roc.eer_threshold,
+            result.tars()
+        );
+        for line in roc.to_csv().lines() {
+            csv.push_str(&format!("{i};{line}\n"));
+        }
+        json.push((i, roc));
+    }
+
+    std::fs::write(format!("roc-{}.csv", data.name()), csv).unwrap();
+    std::fs::write(
+        format!("roc-{}.json", data.name()),
+        serde_json::to_string(&json).unwrap(),
+    )
+    .unwrap();
+}
+
+pub fn random_dims(
+    data: Box<dyn Dataset>,
+    rec: &mut Recognition,
+    amount_dimensions: usize,
+    eval: &Evaluation,
+) {
+    println!("amount_dimensions;indices;{}", eval.variants_header());
+    for _ in 1..101 {
+        let mut rng = rand::thread_rng();
+
+        let mut indices = (0..512).collect::<Vec<_>>();
+        indices.shuffle(&mut rng);
+        indices = indices[..amount_dimensions].to_vec();
+
+        let results = eval
+            .variants()
+            .into_iter()
+            .map(|normalized| {
+                evaluate(data.as_ref(), rec, eval, |emb1, emb2| {
+                    let emb1 = variant(indices.iter().map(|&i| emb1[i]).collect(), normalized);
+                    let emb2 = variant(indices.iter().map(|&i| emb2[i]).collect(), normalized);
+                    eval.metric.calc(&emb1, &emb2)
+                })
+                .unwrap()
+                .calc()
+            })
+            .join(";");
+        println!("{amount_dimensions};{:?};{results}", indices);
+    }
+}
+
+pub fn random_dims_full(data: Box<dyn Dataset>, rec: &mut Recognition, eval: &Evaluation) {
+    println!("amount_dimensions;indices;{}", eval.variants_header());
+    for amount_dimensions in (1..513).rev() {
+        let mut rng = rand::thread_rng();
+
+        let mut indices = (0..512).collect::<Vec<_>>();
+        indices.shuffle(&mut rng);
+        indices = indices[..amount_dimensions].to_vec();
+
+        let results = eval
+            .variants()
+            .into_iter()
+            .map(|normalized| {
+                evaluate(data.as_ref(), rec, eval, |emb1, emb2| {
+                    let emb1 = variant(indices.iter().map(|&i| emb1[i]).collect(), normalized);
+                    let emb2 = variant(indices.iter().map(|&i| emb2[i]).collect(), normalized);
+                    eval.metric.calc(&emb1, &emb2)
+                })
+                .unwrap()
+                .calc()
+            })
+            .join(";");
+        println!("{amount_dimensions};{:?};{results}", indices);
+    }
+}
+
+pub fn best_elements_full(
+    data: Box<dyn Dataset>,
+    rec: &mut Recognition,
+    amount_dim: usize,
+    metric: Distance,
+) {
+    let pairs: Vec<_> = data
+        .embeddings(rec)
+        .into_iter()
+        .filter(is_complete)
+        .collect();
+    let mut possible_indices = Vec::new();
+    for i in 0..amount_dim {
+        possible_indices.push(i);
+    }
+
+    for i in 0..possible_indices.len() {
+        let mut best = (vec![0], 999999999);
+        for perm in tqdm!(possible_indices.iter().copied().combinations(i)) {
+            let mut result = Result::new();
+            for (same_person, emb1, emb2) in &pairs {
+                let dist = metric.calc_indexed(emb1, emb2, &perm);
+                if *same_person {
+                    result.add_same(dist);
+                } else {
+                    result.add_diff(dist);
+                }
+            }
+            let amount_false = result.calc_return_false();
+            if amount_false < best.1 {
+                best = (perm.clone(), amount_false);
+            }
+        }
+        println!(
+            "Best perm with {i} elements: {:?} with a total amount of errors of {}",
+            best.0, best.1
+        );
+    }
+}
+
+pub fn quant(data: Box<dyn Dataset>, rec: &mut Recognition, eval: &Evaluation) {
+    //Quantitize to integer
+    let result = evaluate(data.as_ref(), rec, eval, |emb1, emb2| {
+        eval.metric.calc(emb1, emb2)
+    })
+    .unwrap();
+    println!("Original f32->{}", result.calc());
+
+    println!("scale;min-value;max-value;{}", eval.header());
+
+    for i in 1..200 {
+        let scale = i as f32;
+        let mut min_value = std::i32::MAX;
+        let mut max_value = std::i32::MIN;
+        let result = evaluate(data.as_ref(), rec, eval, |emb1, emb2| {
+            let emb1: Vec<i32> = emb1.iter().map(|&x| (x * scale) as i32).collect();
+            min_value = std::cmp::min(min_value, emb1.iter().min().cloned().unwrap());
+            max_value = std::cmp::max(max_value, emb1.iter().max().cloned().unwrap());
+
+            let emb2: Vec<i32> = emb2.iter().map(|&x| (x * scale) as i32).collect();
+            min_value = std::cmp::min(min_value, emb2.iter().min().cloned().unwrap());
+            max_value = std::cmp::max(max_value, emb2.iter().max().cloned().unwrap());
+
+            eval.metric.calc_int(&emb1, &emb2)
+        })
+        .unwrap();
+        println!("{scale};{min_value};{max_value};{}", result.calc());
+    }
+}
+
+/// Compares product quantization with the scalar quantization of `profile`, printing the
+/// accuracy per amount of bytes per template. The codebooks are trained on `fit`. Pairs are
+/// compared using asymmetric (`pq-adc`, only the second image of each pair is encoded) and
+/// symmetric (`pq-sdc`, both images are encoded) distance computation.
+pub fn product_quantization(
+    data: Box<dyn Dataset>,
+    rec: &mut Recognition,
+    eval: &Evaluation,
+    profile: &ReductionProfile,
+    fit: &[Vec<f32>],
+) {
+    println!("method;bytes_per_template;{}", eval.header());
+    let bits = profile.indices.len() * profile.bits.min(profile.dtype.bits()) as usize;
+    let bytes = (bits + 7) / 8;
+    let result = evaluate_profile(data.as_ref(), rec, eval, profile, false).unwrap();
+    println!("profile;{bytes};{}", result.calc());
+
+    for amount in PQ_SUBSPACES {
+        let pq = ProductQuantizer::train(fit, amount, 0);
+        // Encoding is expensive, so every embedding is only encoded once
+        let mut codes: HashMap<Vec<u32>, Vec<u8>> = HashMap::new();
+        let mut encode = |emb: &[f32]| {
+            codes
+                .entry(key(emb))
+                .or_insert_with(|| pq.encode(emb))
+                .clone()
+        };
+
+        let adc = evaluate(data.as_ref(), rec, eval, |emb1, emb2| {
+            pq.adc(eval.metric, emb1, &encode(emb2))
+        })
+        .unwrap();
+        println!("pq-adc;{};{}", pq.bytes(), adc.calc());
+
+        let sdc = evaluate(data.as_ref(), rec, eval, |emb1, emb2| {
+            pq.sdc(eval.metric, &encode(emb1), &encode(emb2))
+        })
+        .unwrap();
+        println!("pq-sdc;{};{}", pq.bytes(), sdc.calc());
+    }
+}
+
+pub fn proposed(
+    data: Box<dyn Dataset>,
+    rec: &mut Recognition,
+    eval: &Evaluation,
+    profile: &ReductionProfile,
+) {
+    if profile.metric != eval.metric {
+        log::warn!(
+            "Profile has been created for {:?}, but {:?} is used",
+            profile.metric,
+            eval.metric
+        );
+    }
+    if eval.normalize {
+        println!("{}", eval.variants_header());
+    }
+    let results = eval
+        .variants()
+        .into_iter()
+        .map(|normalized| {
+            evaluate_profile(data.as_ref(), rec, eval, profile, normalized)
+                .unwrap()
+                .calc()
+        })
+        .join(";");
+    println!("{results}");
+}
+
+/// Greedily adds the dimension reducing the errors the most. If `profile_path` is supplied, the
+/// selected dimensions are stored there as `ReductionProfile` after every step.
+pub fn best_elements_greedy(
+    data: Box<dyn Dataset>,
+    rec: &mut Recognition,
+    amount_dim: usize,
+    metric: Distance,
+    profile_path: Option<String>,
+) {
+    let pairs: Vec<_> = data
+        .embeddings(rec)
+        .into_iter()
+        .filter(is_complete)
+        .collect();
+    let mut fixed: Vec<usize> = Vec::new();
+
+    for i in 1..amount_dim + 1 {
+        let mut best = (0, 999999999);
+        let mut to_potentially_add: Vec<usize> = (0..amount_dim).map(|x| x as usize).collect();
+        to_potentially_add.retain(|&x| !fixed.contains(&x));
+
+        for to_add in to_potentially_add {
+            let mut perm: Vec<usize> = fixed.clone();
+            perm.push(to_add);
+            let mut result = Result::new();
+            for (same_person, emb1, emb2) in &pairs {
+                let dist = metric.calc_indexed(emb1, emb2, &perm);
+                if *same_person {
+                    result.add_same(dist);
+                } else {
+                    result.add_diff(dist);
+                }
+            }
+            let amount_false = result.calc_return_false();
+            if amount_false < best.1 {
+                best = (to_add, amount_false);
+            }
+            if i == 1 {
+                println!(
+                    "Perm with {i} elements: {to_add} with a total amount of errors of {amount_false}"
+                );
+            }
+        }
+        fixed.push(best.0);
+        println!(
+            "Best perm with {i} elements: {:?} with a total amount of errors of {}",
+            fixed, best.1
+        );
+
+        if let Some(path) = &profile_path {
+            let profile = ReductionProfile {
+                indices: fixed.clone(),
+                scale: 1.,
+                bits: 32,
+                dtype: DType::F32,
+                dataset: data.name(),
+                metric,
+            };
+            profile.save(path).unwrap();
+        }
+    }
+}
+
+pub fn heatmap(data: Box<dyn Dataset>, rec: &mut Recognition, amount_dim: usize, metric: Distance) {
+    println!("idx;neg_impact");
+    let mut impact_index = vec![0_f32; amount_dim];
+    for (same_person, emb1, emb2) in data.embeddings(rec).into_iter().filter(is_complete) {
+        for index in 0..amount_dim {
+            let cur_impact = metric.calc(&emb1[index..index + 1], &emb2[index..index + 1]);
+            match same_person {
+                true => impact_index[index] -= cur_impact,
+                false => impact_index[index] += cur_impact,
+            }
+        }
+    }
+
+    //Normalize to [0;1] range
+    let min = *impact_index
+        .iter()
+        .min_by(|x, y| x.partial_cmp(y).unwrap())
+        .unwrap();
+    let max = *impact_index
+        .iter()
+        .max_by(|x, y| x.partial_cmp(y).unwrap())
+        .unwrap();
+
+    // Normalize
+    for i in 0..impact_index.len() {
+        impact_index[i] = (impact_index[i] - min) / (max - min);
+    }
+
+    for (idx, value) in impact_index.iter().enumerate() {
+        println!("{};{}", idx, value);
+    }
+}
+
+/// Writes all pairs once with full embeddings to `embeddings_full.json` and once reduced by
+/// `profile` to `embeddings_<amount of dimensions>.json`, one JSON array per line.
+pub fn extract_emb(data: Box<dyn Dataset>, rec: &mut Recognition, profile: &ReductionProfile) {
+    let mut full = Vec::new();
+    let mut comp = Vec::new();
+    for (same_person, emb1, emb2) in data.embeddings(rec).into_iter().filter(is_complete) {
+        full.push(serde_json::to_string(&(same_person, &emb1, &emb2)).unwrap());
+
+        let emb1 = profile.apply(&emb1);
+        let emb2 = profile.apply(&emb2);
+        let line = match profile.dtype {
+            DType::F32 => serde_json::to_string(&(same_person, emb1, emb2)),
+            _ => {
+                let emb1: Vec<i32> = emb1.iter().map(|&x| x as i32).collect();
+                let emb2: Vec<i32> = emb2.iter().map(|&x| x as i32).collect();
+                serde_json::to_string(&(same_person, emb1, emb2))
+            }
+        };
+        comp.push(line.unwrap());
+    }
+
+    let mut file = File::create("embeddings_full.json").unwrap();
+    file.write_all(full.join("\n").as_bytes()).unwrap();
+
+    let mut file = File::create(format!("embeddings_{}.json", profile.indices.len())).unwrap();
+    file.write_all(comp.join("\n").as_bytes()).unwrap();
+}
+
+/// Prints the amount of unique images, identities and pairs, followed by the amount of pairs of
+/// each identity.
+pub fn stats(data: Box<dyn Dataset>) {
+    let stats = data.stats();
+    println!("images;identities;same_pairs;diff_pairs");
+    println!(
+        "{};{};{};{}",
+        stats.images,
+        stats.pairs_per_identity.len(),
+        stats.same_pairs,
+        stats.diff_pairs
+    );
+
+    println!("identity;pairs");
+    for (identity, pairs) in &stats.pairs_per_identity {
+        println!("{identity};{pairs}");
+    }
+}
+
+/// Evaluates 1:N identification (see `identification::Split`) using the full embeddings, the
+/// embeddings truncated to and randomly reduced to as many dimensions as `profile` keeps, and the
+/// embeddings reduced by `profile`. Prints the rank-1 and rank-5 identification rate and the
+/// detection and identification rate at each of `fpirs`, the CMC curves are written to
+/// `cmc-<dataset>.csv`. The random dimensions are drawn using `seed`.
+pub fn identification(
+    data: Box<dyn Dataset>,
+    rec: &mut Recognition,
+    metric: Distance,
+    profile: &ReductionProfile,
+    fpirs: &[f32],
+    seed: u64,
+) {
+    let split = Split::new(data.as_ref(), rec);
+    log::info!(
+        "{} enrolled identities, {} mated and {} non-mated probes",
+        split.gallery.len(),
+        split.mated.len(),
+        split.non_mated.len()
+    );
+
+    let amount_dimensions = profile.indices.len();
+    let mut indices = (0..512).collect::<Vec<_>>();
+    indices.shuffle(&mut StdRng::seed_from_u64(seed));
+    indices.truncate(amount_dimensions);
+
+    let results: Vec<(&str, usize, Identification)> = vec![
+        ("full", 512, split.evaluate(metric, CMC_RANKS, fpirs)),
+        (
+            "truncated",
+            amount_dimensions,
+            split
+                .map(|emb| emb[..amount_dimensions].to_vec())
+                .evaluate(metric, CMC_RANKS, fpirs),
+        ),
+        (
+            "random",
+            amount_dimensions,
+            split
+                .map(|emb| indices.iter().map(|&i| emb[i]).collect())
+                .evaluate(metric, CMC_RANKS, fpirs),
+        ),
+        (
+            "profile",
+            amount_dimensions,
+            split
+                .map(|emb| profile.apply(emb))
+                .evaluate(metric, CMC_RANKS, fpirs),
+        ),
+    ];
+
+    let fpir_header: String = fpirs
+        .iter()
+        .map(|fpir| format!(";dir@fpir={fpir}"))
+        .collect();
+    println!("reduction;amount_dimensions;rank1;rank5{fpir_header}");
+    let mut csv = String::from("reduction;rank;identification_rate\n");
+    for (reduction, dimensions, result) in &results {
+        let dir: String = result.dir.iter().map(|dir| format!(";{dir}")).collect();
+        println!(
+            "{reduction};{dimensions};{};{}{dir}",
+            result.rank(1),
+            result.rank(5)
+        );
+        for (rank, rate) in result.cmc.iter().enumerate() {
+            csv.push_str(&format!("{reduction};{};{rate}\n", rank + 1));
+        }
+    }
+    std::fs::write(format!("cmc-{}.csv", data.name()), csv).unwrap();
+}
+
+/// Evaluates template-based verification with every `Fusion` using the full embeddings, the
+/// embeddings truncated to as many dimensions as `profile` keeps and the embeddings reduced by
+/// `profile`. Each embedding is reduced before fusion.
+///
+/// # Panics
+/// - Panics if `data` does not consist of templates
+pub fn template_verification(
+    data: Box<dyn Dataset>,
+    rec: &mut Recognition,
+    eval: &Evaluation,
+    profile: &ReductionProfile,
+) {
+    let templates = data
+        .templates()
+        .expect("Template verification requires --data templates");
+    let embeddings = templates.embeddings(rec);
+    let amount_dimensions = profile.indices.len();
+
+    println!("fusion;reduction;amount_dimensions;{}", eval.header());
+    for fusion in Fusion::ALL {
+        let results = [
+            (
+                "full",
+                512,
+                templates.evaluate(&embeddings, eval, rec.missing, fusion, |emb| emb.to_vec()),
+            ),
+            (
+                "truncated",
+                amount_dimensions,
+                templates.evaluate(&embeddings, eval, rec.missing, fusion, |emb| {
+                    emb[..amount_dimensions].to_vec()
+                }),
+            ),
+            (
+                "profile",
+                amount_dimensions,
+                templates.evaluate(&embeddings, eval, rec.missing, fusion, |emb| {
+                    profile.apply(emb)
+                }),
+            ),
+        ];
+        for (reduction, dimensions, result) in results {
+            println!(
+                "{};{reduction};{dimensions};{}",
+                fusion.name(),
+                result.calc()
+            );
+        }
+    }
+}
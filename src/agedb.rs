@@ -0,0 +1,112 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+use std::path::Path;
+
+use crate::{Dataset, IsSamePerson, AMOUNT_FOLDS};
+
+#[derive(Debug)]
+/// All possible AgeDb Errors
+pub enum Error {
+    //Can't find pairs file
+    CsvError(csv::Error),
+
+    /// A line does not consist of `<label> <image1> <image2>`
+    InvalidLine(usize),
+}
+
+impl From<csv::Error> for Error {
+    fn from(value: csv::Error) -> Self {
+        Self::CsvError(value)
+    }
+}
+
+/// Represents the image pairs of the AgeDB-30 protocol (pairs with an age gap of 30 years).
+///
+/// Each line of the pairs file consists of `<label> <image1> <image2>`, the label is `1` for the
+/// same person and `0` for different people. The 6000 pairs form `AMOUNT_FOLDS` consecutive
+/// folds of 600 pairs each. Images are named `<id>_<identity>_<age>_<gender>.jpg`.
+pub struct AgeDb {
+    pairs: Vec<(IsSamePerson, String, String)>,
+}
+
+impl AgeDb {
+    pub fn new(pairs_file: &str, basepath: String) -> Result<Self, Error> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(b' ')
+            .from_path(pairs_file)?;
+
+        let mut pairs = Vec::new();
+        for (idx, record) in reader.records().enumerate() {
+            let record = record?;
+            if record.len() != 3 {
+                return Err(Error::InvalidLine(idx + 1));
+            }
+            pairs.push((
+                &record[0] == "1",
+                format!("{basepath}/{}", &record[1]),
+                format!("{basepath}/{}", &record[2]),
+            ));
+        }
+
+        Ok(Self { pairs })
+    }
+}
+
+impl Dataset for AgeDb {
+    fn pairs(&self) -> Vec<(bool, String, String)> {
+        self.pairs.clone()
+    }
+
+    fn fold(&self, idx: usize) -> usize {
+        idx * AMOUNT_FOLDS / self.pairs.len()
+    }
+
+    fn identity(&self, path: &str) -> String {
+        let stem = Path::new(path)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        match stem.split('_').nth(1) {
+            Some(identity) => identity.into(),
+            None => stem.into(),
+        }
+    }
+
+    fn name(&self) -> String {
+        "agedb30".into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fold_with_less_pairs_than_folds() {
+        let path =
+            std::env::temp_dir().join(format!("reducedemb-agedb-{}.txt", std::process::id()));
+        std::fs::write(&path, "1 a.jpg b.jpg\n0 a.jpg c.jpg\n1 c.jpg d.jpg\n").unwrap();
+        let data = AgeDb::new(path.to_str().unwrap(), String::new()).unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let folds: Vec<usize> = (0..3).map(|idx| data.fold(idx)).collect();
+        assert_eq!(folds, [0, 3, 6]);
+    }
+}
@@ -15,15 +15,43 @@
 // See the Licence for the specific language governing permissions and
 // limitations under the Licence.
 
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use crate::cache;
+use crate::failure::{Failure, MissingFace, Reason};
+use crate::IsSamePerson;
+
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+
+use kdam::tqdm;
 
 use face::{
     detection::{retinaface, DetectionError},
     recognition::arcface,
 };
 
+use crate::detection::{Detector, FaceDetector, FacePolicy};
+
 type Embedding = Vec<f32>;
 
+/// Policy name of cached embeddings calculated of the image center (`MissingFace::CenterCrop`).
+const CENTER_CROP_POLICY: &str = "center-crop";
+
+/// Side length of the center crop relative to the shorter side of the image. Roughly matches the
+/// size of the face in the loosely cropped images of LFW and CPLFW.
+const CENTER_CROP_SIZE: f32 = 0.5;
+
+/// Size of the (square) input image of arcface
+const ARCFACE_INPUT_SIZE: u32 = 112;
+
 #[derive(Debug)]
 /// Defines all possible error for recognition.
 pub enum Error {
@@ -35,6 +63,9 @@ pub enum Error {
 
     ///Fastdet threw an error
     FastdetError(DetectionError),
+
+    /// Binary cache could not be read
+    CacheError(cache::Error),
 }
 
 impl From<serde_json::Error> for Error {
@@ -54,13 +85,82 @@ impl From<DetectionError> for Error {
     }
 }
 
+impl From<cache::Error> for Error {
+    fn from(value: cache::Error) -> Self {
+        Self::CacheError(value)
+    }
+}
+
+/// Outcome of calculating the embedding of a single image.
+enum Calculated {
+    /// Embedding of the face chosen by the face selection policy
+    Face(Embedding),
+
+    /// No usable face found. Contains the embedding of the center crop, if requested.
+    Failure(Failure, Option<Embedding>),
+}
+
+/// Calculates the embedding of the center of `img`, without face detection.
+fn center_crop(arcface: &arcface::ArcFace, img: &DynamicImage) -> Embedding {
+    let (width, height) = img.dimensions();
+    let size = (width.min(height) as f32 * CENTER_CROP_SIZE) as u32;
+    let image = img
+        .crop_imm((width - size) / 2, (height - size) / 2, size, size)
+        .resize_exact(ARCFACE_INPUT_SIZE, ARCFACE_INPUT_SIZE, FilterType::Triangle);
+
+    arcface.calc_emb(&image).unwrap().into() // unwrap okay; because it only fails if network is wrong
+}
+
+/// Detects the faces of `filename` and calculates the embedding of the face chosen by `policy`.
+/// If there is no usable face and `with_center_crop` is set, the center crop is used instead.
+///
+/// # Panics
+/// - Panics if `filename` can't be read (panic occurs inside `img_read` macro
+fn calculate(
+    face: &dyn FaceDetector,
+    arcface: &arcface::ArcFace,
+    policy: FacePolicy,
+    filename: &Path,
+    with_center_crop: bool,
+) -> Calculated {
+    let img = face::img_read!(&filename);
+    let res = face.detect(&img).unwrap();
+    if let Some(idx) = policy.select(&res, &img) {
+        let image = face::warp::Warp::perform(&img, &res[idx].landmarks);
+
+        let emb = arcface.calc_emb(&image).unwrap(); // unwrap okay; because it only fails if network is wrong
+        return Calculated::Face(emb.into());
+    }
+
+    let failure = Failure {
+        path: filename.to_path_buf(),
+        reason: match res.is_empty() {
+            true => Reason::NoFace,
+            false => Reason::NotSelected,
+        },
+        faces: res.len(),
+    };
+    let emb = with_center_crop.then(|| center_crop(arcface, &img));
+    Calculated::Failure(failure, emb)
+}
+
 /// Caches arcfaces' results
 pub struct Recognition {
     /// Calculated embeddings for `filepath`s
-    pub emb: HashMap<PathBuf, Embedding>,
+    pub emb: HashMap<PathBuf, cache::Entry>,
 
     /// Face detection model
-    pub face: retinaface::Retinaface,
+    pub face: Box<dyn FaceDetector>,
+
+    /// Defines which face is used if multiple faces are detected. Cached embeddings calculated
+    /// with another policy are recalculated.
+    pub policy: FacePolicy,
+
+    /// Defines how pairs are handled if no embedding is available for one of their images
+    pub missing: MissingFace,
+
+    /// Images without usable face found by `cache_img`
+    pub failures: Vec<Failure>,
 
     /// Face recognition model
     pub arcface: arcface::ArcFace,
@@ -68,6 +168,13 @@ pub struct Recognition {
     /// Path to cache file. If None, nothing is cached
     cache_path: Option<PathBuf>,
 
+    /// Whether an incomplete last record of the cache file has been removed before appending
+    cache_repaired: bool,
+
+    /// Used to create the models of the workers of `cache_parallel`
+    detector: Detector,
+    arcface_model: String,
+
     #[cfg(test)]
     amount_new_calculated: u32,
 }
@@ -75,100 +182,232 @@ pub struct Recognition {
 impl Recognition {
     //TODO: impl Default-trait
     pub fn default(name: &str) -> Self {
-        Recognition::new(
-            Some(PathBuf::from(format!("data/cache-{}-250x250.json", name))),
-            "data/models/retinaface-250x250.tflite",
-            "data/models/retinaface-anchors-250x250.json",
-            "data/models/arcface.tflite",
-        )
-        .unwrap()
+        Recognition::with_detector(name, Detector::Retinaface)
+    }
+
+    /// Creates a new `Recognition` struct for dataset `name` using `detector`. Each detector uses
+    /// its own cache file.
+    pub fn with_detector(name: &str, detector: Detector) -> Self {
+        let path = PathBuf::from(format!("data/cache-{}-{}.bin", name, detector.name()));
+        let json = path.with_extension("json");
+        if !path.exists() && json.exists() {
+            let amount = cache::convert(&json, &path).unwrap();
+            log::info!("Converted {amount} embeddings from {json:?} to {path:?}");
+        }
+
+        Recognition::new(Some(path), detector, "data/models/arcface.tflite").unwrap()
     }
 
     /// Creates a new `Recognition` struct.
     ///
     /// # Arguments
     /// - `path` if supplied, all calculations are cached there. If non existing file is supplied,
-    /// the file will be created.
-    /// - `retinaface_model`: TF Lite model file of retinaface
-    /// - `retinaface_anchor`: Anchor settings used for `retinaface_model`
+    /// the file will be created. Files ending with `.json` use the (slow) JSON format, all other
+    /// files the binary format of `cache`.
+    /// - `detector`: Face detector used to find the face in each image
     /// - `arcface_model`: TF Lite model file of arcface
     ///
     /// # Errors
     /// - `Error::DeserializeError` returned, if supplied cache file is not deserializable
-    /// - `Error::RetinafaceError` returned, if creation of Retinaface threw an error, e.g. because
-    /// models could not be found
+    /// - `Error::CacheError` returned, if supplied binary cache file is not readable
+    /// - `Error::RetinafaceError` or `Error::FastdetError` returned, if creation of the detector
+    /// threw an error, e.g. because models could not be found
     pub fn new(
         path: Option<PathBuf>,
-        retinaface_model: &str,
-        retinaface_anchors: &str,
+        detector: Detector,
         arcface_model: &str,
     ) -> Result<Self, Error> {
         let mut emb = HashMap::new();
         if let Some(path) = &path {
-            emb = match std::fs::read_to_string(path) {
-                Ok(data) => serde_json::from_str(&data)?,
-                Err(_) => HashMap::new(),
-            };
+            if cache::is_json(path) {
+                emb = match std::fs::read_to_string(path) {
+                    Ok(data) => cache::from_json(serde_json::from_str(&data)?),
+                    Err(_) => HashMap::new(),
+                };
+            } else {
+                emb = cache::load(path)?;
+            }
         }
 
         log::info!("Loaded {} embeddings from cache", emb.len());
 
-        //let face = face::fast::FastInference::new(0.7).unwrap();
-        let face =
-            face::detection::retinaface::Retinaface::new(retinaface_model, retinaface_anchors)?;
+        let face = detector.create()?;
         let arcface = face::recognition::arcface::ArcFace::new(arcface_model);
 
         Ok(Self {
             emb,
             face,
+            policy: FacePolicy::MostCentered,
+            missing: MissingFace::Skip,
+            failures: Vec::new(),
             arcface,
             cache_path: path,
+            cache_repaired: false,
+            detector,
+            arcface_model: arcface_model.into(),
             #[cfg(test)]
             amount_new_calculated: 0,
         })
     }
 
-    fn add(&mut self, filename: PathBuf, emb: Embedding) {
-        self.emb.insert(filename, emb);
+    fn add(&mut self, filename: PathBuf, emb: Embedding, policy: &str) {
+        let entry = cache::Entry {
+            emb,
+            policy: policy.into(),
+        };
         if let Some(path) = &self.cache_path {
-            let j = serde_json::to_string(&self.emb).unwrap();
-            std::fs::write(path, j).expect("Unable to write file");
+            if cache::is_json(path) {
+                self.emb.insert(filename, entry);
+                let emb: HashMap<&PathBuf, &Embedding> =
+                    self.emb.iter().map(|(k, v)| (k, &v.emb)).collect();
+                let j = serde_json::to_string(&emb).unwrap();
+                std::fs::write(path, j).expect("Unable to write file");
+                return;
+            }
+            if !self.cache_repaired {
+                cache::repair(path).expect("Unable to repair cache file");
+                self.cache_repaired = true;
+            }
+            cache::append(path, &filename, &entry).expect("Unable to write file");
         }
+        self.emb.insert(filename, entry);
     }
 
     /// Retrieves an cached embedding
     pub fn get(&self, filename: PathBuf) -> Option<&Vec<f32>> {
-        self.emb.get(&filename)
+        self.emb.get(&filename).map(|entry| &entry.emb)
+    }
+
+    /// Returns the embedding of `filename` calculated with the current `policy`. If there is none,
+    /// the embedding of the image center is returned (and cached) for `MissingFace::CenterCrop`.
+    ///
+    /// # Panics
+    /// - Panics if the center crop of `filename` has to be calculated, but `filename` can't be read
+    pub fn embedding(&mut self, filename: &str) -> Option<Vec<f32>> {
+        let filename = PathBuf::from(filename);
+        let with_center_crop = self.missing == MissingFace::CenterCrop;
+        match self.emb.get(&filename) {
+            Some(entry) if entry.policy == self.policy.name() => return Some(entry.emb.clone()),
+            Some(entry) if entry.policy == CENTER_CROP_POLICY && with_center_crop => {
+                return Some(entry.emb.clone())
+            }
+            _ => {}
+        }
+
+        if with_center_crop {
+            let img = face::img_read!(&filename);
+            let emb = center_crop(&self.arcface, &img);
+            self.add(filename, emb.clone(), CENTER_CROP_POLICY);
+            return Some(emb);
+        }
+        None
+    }
+
+    /// Returns the embeddings of both images of a pair. Pairs with a missing embedding are
+    /// handled according to `missing`: `None` is returned for `MissingFace::Skip`, empty
+    /// embeddings for `MissingFace::FalseReject`.
+    pub fn pair(
+        &mut self,
+        same_person: IsSamePerson,
+        path1: &str,
+        path2: &str,
+    ) -> Option<(IsSamePerson, Vec<f32>, Vec<f32>)> {
+        match (self.embedding(path1), self.embedding(path2)) {
+            (Some(emb1), Some(emb2)) => Some((same_person, emb1, emb2)),
+            _ if self.missing == MissingFace::FalseReject => {
+                Some((same_person, Vec::new(), Vec::new()))
+            }
+            _ => None,
+        }
     }
 
     /// Caches an embedding.
     ///
-    /// If `filename` is already in the cache, nothing is done.
-    /// If multiple faces are detected, the one most in the middle is used.
+    /// If `filename` is already in the cache (calculated with the same `policy`), nothing is done.
+    /// If multiple faces are detected, `policy` decides which one is used. If no face is usable,
+    /// the image is added to `failures` (and the center crop is cached for
+    /// `MissingFace::CenterCrop`).
     ///
     /// # Panics
     /// - Panics if `filename` can't be read (panic occurs inside `img_read` macro
     pub fn cache_img(&mut self, filename: &PathBuf) {
-        if !self.emb.contains_key(filename) {
+        if let Some(with_center_crop) = self.pending(filename) {
             //#[cfg(test)] uncommenting these two lines gives compiler error ?!? TODO GS
             //self.amount_new_calculated += 1;
-            let img = face::img_read!(&filename);
-            let res = self.face.inference(&img).unwrap();
-            if !res.is_empty() {
-                let idx_most_centered_face = res
-                    .iter()
-                    .map(|a| (125. - a.landmarks.nose.x).abs() + (125. - a.landmarks.nose.y).abs())
-                    .enumerate()
-                    .min_by(|(_, a), (_, b)| a.total_cmp(b))
-                    .map(|(index, _)| index)
-                    .unwrap();
-
-                let image = face::warp::Warp::perform(&img, &res[idx_most_centered_face].landmarks);
-
-                let emb = self.arcface.calc_emb(&image).unwrap(); // unwrap okay; because it only fails if network is wrong
-                self.add(filename.clone(), emb.into());
-            } else {
-                println!("Ignored {filename:?}, {} faces found", res.len());
+            let calculated = calculate(
+                self.face.as_ref(),
+                &self.arcface,
+                self.policy,
+                filename,
+                with_center_crop,
+            );
+            self.store(filename.clone(), calculated);
+        }
+    }
+
+    /// Same as calling `cache_img` for all `filenames`, but the embeddings are calculated by
+    /// `workers` threads. Each worker loads its own face detector and arcface model, the results
+    /// are merged into this cache.
+    ///
+    /// # Panics
+    /// - Panics if a worker can't load its models or an image can't be read
+    pub fn cache_parallel(&mut self, filenames: &[PathBuf], workers: usize) {
+        let mut todo: Vec<(&PathBuf, bool)> = filenames
+            .iter()
+            .filter_map(|filename| Some((filename, self.pending(filename)?)))
+            .collect();
+        todo.sort();
+        todo.dedup();
+
+        let next = AtomicUsize::new(0);
+        let (sender, receiver) = mpsc::channel();
+        let (detector, policy) = (self.detector, self.policy);
+        let arcface_model = self.arcface_model.clone();
+        thread::scope(|s| {
+            for _ in 0..workers {
+                let (sender, todo, next, arcface_model) =
+                    (sender.clone(), &todo, &next, &arcface_model);
+                s.spawn(move || {
+                    let face = detector.create().unwrap();
+                    let arcface = arcface::ArcFace::new(arcface_model);
+                    while let Some((filename, with_center_crop)) =
+                        todo.get(next.fetch_add(1, Ordering::Relaxed))
+                    {
+                        let calculated =
+                            calculate(face.as_ref(), &arcface, policy, filename, *with_center_crop);
+                        sender.send(((*filename).clone(), calculated)).unwrap();
+                    }
+                });
+            }
+            // Only the workers' senders are left, so the loop ends once all workers are done
+            drop(sender);
+
+            for (filename, calculated) in tqdm!(receiver.iter(), total = todo.len()) {
+                self.store(filename, calculated);
+            }
+        });
+    }
+
+    /// Returns `None` if the embedding of `filename` is already cached with the current `policy`,
+    /// otherwise whether the center crop has to be calculated if no face is usable.
+    fn pending(&self, filename: &PathBuf) -> Option<bool> {
+        let cached = self.emb.get(filename).map(|entry| entry.policy.as_str());
+        if cached == Some(self.policy.name()) {
+            return None;
+        }
+        Some(self.missing == MissingFace::CenterCrop && cached != Some(CENTER_CROP_POLICY))
+    }
+
+    /// Adds the outcome of `calculate` to the cache.
+    fn store(&mut self, filename: PathBuf, calculated: Calculated) {
+        match calculated {
+            Calculated::Face(emb) => self.add(filename, emb, self.policy.name()),
+            Calculated::Failure(failure, emb) => {
+                println!("Ignored {filename:?}, {} faces found", failure.faces);
+                self.failures.push(failure);
+                if let Some(emb) = emb {
+                    self.add(filename, emb, CENTER_CROP_POLICY);
+                }
             }
         }
     }
@@ -182,8 +421,7 @@ impl Recognition {
 //    fn test_no_cachefile() {
 //        let mut r = Recognition::new(
 //            None,
-//            "data/models/retinaface-250x250.tflite",
-//            "data/models/retinaface-anchors-250x250.json",
+//            Detector::Retinaface,
 //            "data/models/arcface.tflite",
 //        )
 //        .unwrap();
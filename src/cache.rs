@@ -0,0 +1,295 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+//! Binary embedding cache.
+//!
+//! The file starts with `MAGIC`, followed by one record per embedding:
+//! - `u32` length of the path in bytes, followed by the UTF-8 encoded path
+//! - `u32` length of the face selection policy in bytes, followed by the UTF-8 encoded policy
+//! - `u32` amount of dimensions, followed by the dimensions as `f32`
+//!
+//! All numbers are little endian. New embeddings are appended, so the file never has to be
+//! rewritten. If a path occurs multiple times, the last record is used.
+
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// Identifies the file format (incl. version).
+const MAGIC: &[u8; 8] = b"REDEMB02";
+
+/// Face selection policy of embeddings from JSON caches, which don't store a policy. These have
+/// been created using the nose closest to (125, 125), i.e. the center of the 250x250 images
+/// of LFW and CPLFW.
+pub const LEGACY_POLICY: &str = "most-centered";
+
+#[derive(Debug, Clone)]
+/// Single cached embedding.
+pub struct Entry {
+    pub emb: Vec<f32>,
+
+    /// Name of the face selection policy used to pick the face the embedding is calculated of
+    pub policy: String,
+}
+
+#[derive(Debug)]
+/// All possible errors of the binary cache.
+pub enum Error {
+    /// Cache file could not be read or written
+    IoError(std::io::Error),
+
+    /// JSON cache could not be deserialized
+    DeserializeError(serde_json::Error),
+
+    /// File is not a binary cache
+    InvalidFormat,
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Self::DeserializeError(value)
+    }
+}
+
+/// Returns true if `path` is a (legacy) JSON cache, based on its extension.
+pub fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
+
+fn encode(buf: &mut Vec<u8>, filename: &Path, entry: &Entry) {
+    let filename = filename.to_string_lossy();
+    buf.extend((filename.len() as u32).to_le_bytes());
+    buf.extend(filename.as_bytes());
+    buf.extend((entry.policy.len() as u32).to_le_bytes());
+    buf.extend(entry.policy.as_bytes());
+    buf.extend((entry.emb.len() as u32).to_le_bytes());
+    for x in &entry.emb {
+        buf.extend(x.to_le_bytes());
+    }
+}
+
+/// Reads a little endian `u32` at `pos` and advances `pos`.
+fn read_u32(data: &[u8], pos: &mut usize) -> Option<u32> {
+    let bytes = data.get(*pos..*pos + 4)?;
+    *pos += 4;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Reads a length-prefixed UTF-8 string at `pos` and advances `pos`.
+fn read_string(data: &[u8], pos: &mut usize) -> Option<String> {
+    let len = read_u32(data, pos)? as usize;
+    let ret = String::from_utf8_lossy(data.get(*pos..*pos + len)?).to_string();
+    *pos += len;
+    Some(ret)
+}
+
+/// Reads a single record at `pos` and advances `pos`. Returns `None` if the record is incomplete.
+fn read_record(data: &[u8], pos: &mut usize) -> Option<(PathBuf, Entry)> {
+    let filename = read_string(data, pos)?;
+    let policy = read_string(data, pos)?;
+
+    let dims = read_u32(data, pos)? as usize;
+    let emb = data
+        .get(*pos..*pos + dims * 4)?
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect();
+    *pos += dims * 4;
+    Some((PathBuf::from(filename), Entry { emb, policy }))
+}
+
+/// Reads all complete records of a binary cache. Returns the embeddings and the length of the
+/// complete records (incl. magic), i.e. where an incomplete last record starts.
+fn read_records(data: &[u8]) -> (HashMap<PathBuf, Entry>, usize) {
+    let mut ret = HashMap::new();
+    let mut pos = MAGIC.len();
+    while pos < data.len() {
+        let start = pos;
+        match read_record(data, &mut pos) {
+            Some((filename, emb)) => {
+                ret.insert(filename, emb);
+            }
+            None => return (ret, start),
+        }
+    }
+    (ret, pos)
+}
+
+/// Loads all embeddings of the binary cache `path`. A non-existing file results in an empty cache.
+/// An incomplete last record, e.g. because the program has been killed or is still appending, is
+/// skipped.
+///
+/// # Errors
+/// - `Error::IoError` returned, if `path` exists but can't be read
+/// - `Error::InvalidFormat` returned, if `path` is not a binary cache
+pub fn load(path: &Path) -> Result<HashMap<PathBuf, Entry>, Error> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    if !data.starts_with(MAGIC) {
+        return Err(Error::InvalidFormat);
+    }
+
+    let (ret, len) = read_records(&data);
+    if len < data.len() {
+        log::warn!("Skipping incomplete last record of {path:?}");
+    }
+    Ok(ret)
+}
+
+/// Removes an incomplete last record of the binary cache `path`, e.g. because the program has been
+/// killed while appending. Records appended later would otherwise follow the incomplete one and be
+/// lost. Has to be called before the first `append` of a process, while no other process writes
+/// to `path`. Non-existing files are ignored.
+///
+/// # Errors
+/// - `Error::IoError` returned, if `path` exists but can't be read or truncated
+/// - `Error::InvalidFormat` returned, if `path` is not a binary cache
+pub fn repair(path: &Path) -> Result<(), Error> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if data.is_empty() {
+        return Ok(());
+    }
+    if !data.starts_with(MAGIC) {
+        return Err(Error::InvalidFormat);
+    }
+
+    let (_, len) = read_records(&data);
+    if len < data.len() {
+        log::warn!("Removing incomplete last record of {path:?}");
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(len as u64)?;
+    }
+    Ok(())
+}
+
+/// Appends a single embedding to the binary cache `path`. The file is created if it doesn't
+/// exist.
+///
+/// # Errors
+/// - `Error::IoError` returned, if `path` can't be written
+pub fn append(path: &Path, filename: &Path, entry: &Entry) -> Result<(), Error> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+    let mut buf = Vec::new();
+    if file.metadata()?.len() == 0 {
+        buf.extend(MAGIC);
+    }
+    encode(&mut buf, filename, entry);
+    file.write_all(&buf)?;
+    Ok(())
+}
+
+/// Writes all embeddings of `emb` to the binary cache `path`, overwriting existing files.
+///
+/// # Errors
+/// - `Error::IoError` returned, if `path` can't be written
+pub fn write(path: &Path, entries: &HashMap<PathBuf, Entry>) -> Result<(), Error> {
+    let mut file = BufWriter::new(std::fs::File::create(path)?);
+    file.write_all(MAGIC)?;
+
+    let mut buf = Vec::new();
+    for (filename, entry) in entries {
+        buf.clear();
+        encode(&mut buf, filename, entry);
+        file.write_all(&buf)?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Converts the embeddings of a JSON cache to entries with `LEGACY_POLICY`.
+pub fn from_json(emb: HashMap<PathBuf, Vec<f32>>) -> HashMap<PathBuf, Entry> {
+    emb.into_iter()
+        .map(|(filename, emb)| {
+            let policy = LEGACY_POLICY.into();
+            (filename, Entry { emb, policy })
+        })
+        .collect()
+}
+
+/// Converts the JSON cache `json` to the binary cache `bin`. Returns the amount of embeddings.
+///
+/// # Errors
+/// - `Error::IoError` returned, if `json` can't be read or `bin` can't be written
+/// - `Error::DeserializeError` returned, if `json` is not a valid JSON cache
+pub fn convert(json: &Path, bin: &Path) -> Result<usize, Error> {
+    let emb: HashMap<PathBuf, Vec<f32>> = serde_json::from_str(&std::fs::read_to_string(json)?)?;
+    let entries = from_json(emb);
+    write(bin, &entries)?;
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detection::FacePolicy;
+
+    fn entry(x: f32) -> Entry {
+        Entry {
+            emb: vec![x; 4],
+            policy: FacePolicy::Largest.name().into(),
+        }
+    }
+
+    #[test]
+    fn append_after_incomplete_record() {
+        let path =
+            std::env::temp_dir().join(format!("reducedemb-cache-{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        append(&path, Path::new("a.jpg"), &entry(1.)).unwrap();
+        append(&path, Path::new("b.jpg"), &entry(2.)).unwrap();
+        let len = std::fs::metadata(&path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 3).unwrap();
+
+        // Loading doesn't modify the file, e.g. while another process is appending
+        let loaded = load(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[Path::new("a.jpg")].emb, vec![1.; 4]);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len - 3);
+
+        repair(&path).unwrap();
+        append(&path, Path::new("c.jpg"), &entry(3.)).unwrap();
+        let loaded = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[Path::new("c.jpg")].emb, vec![3.; 4]);
+        assert_eq!(
+            loaded[Path::new("c.jpg")].policy,
+            FacePolicy::Largest.name()
+        );
+    }
+}
@@ -0,0 +1,257 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+use std::path::Path;
+
+use crate::{Dataset, IsSamePerson, AMOUNT_FOLDS};
+
+#[derive(Debug)]
+/// All possible Cfp Errors
+pub enum Error {
+    /// Image list or split file could not be read
+    IoError(std::io::Error),
+
+    /// Line of an image list or split file could not be parsed
+    InvalidLine(String),
+
+    /// Split file references an image not contained in the image list
+    UnknownImage(usize),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+/// Represents the frontal-profile (FP) protocol of the CFP dataset.
+///
+/// `Protocol/Pair_list_F.txt` and `Protocol/Pair_list_P.txt` list all frontal and profile images
+/// (`<index> <path relative to Protocol>`). Each of the `AMOUNT_FOLDS` splits
+/// (`Protocol/Split/FP/01` to `10`) contains `same.txt` and `diff.txt`, listing pairs as
+/// `<frontal index>,<profile index>`.
+pub struct Cfp {
+    /// All pairs, together with the split they belong to
+    pairs: Vec<(usize, IsSamePerson, String, String)>,
+}
+
+/// Reads an image list, the returned vector contains the path of image `i` at index `i - 1`.
+fn read_images(protocol: &str, list: &str) -> Result<Vec<String>, Error> {
+    let mut ret = Vec::new();
+    for line in std::fs::read_to_string(format!("{protocol}/{list}"))?.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [_, path] => ret.push(format!("{protocol}/{path}")),
+            _ => return Err(Error::InvalidLine(line.into())),
+        }
+    }
+    Ok(ret)
+}
+
+/// Reads a split file, returning the (1-based) frontal and profile index of each pair.
+fn read_split(path: &str) -> Result<Vec<(usize, usize)>, Error> {
+    let mut ret = Vec::new();
+    for line in std::fs::read_to_string(path)?.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let indices = line
+            .split(',')
+            .map(|idx| idx.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>();
+        match indices.as_deref() {
+            Ok([frontal, profile]) => ret.push((*frontal, *profile)),
+            _ => return Err(Error::InvalidLine(line.into())),
+        }
+    }
+    Ok(ret)
+}
+
+impl Cfp {
+    /// Loads the FP protocol of the CFP dataset located at `basepath` (folder containing
+    /// `Protocol` and `Data`).
+    ///
+    /// # Errors
+    /// - `Error::IoError` returned, if a protocol file can't be read
+    /// - `Error::InvalidLine` or `Error::UnknownImage` returned, if a protocol file is malformed
+    pub fn new(basepath: String) -> Result<Self, Error> {
+        let protocol = format!("{basepath}/Protocol");
+        let frontal = read_images(&protocol, "Pair_list_F.txt")?;
+        let profile = read_images(&protocol, "Pair_list_P.txt")?;
+        let image = |images: &Vec<String>, idx: usize| {
+            idx.checked_sub(1)
+                .and_then(|idx| images.get(idx))
+                .cloned()
+                .ok_or(Error::UnknownImage(idx))
+        };
+
+        let mut pairs = Vec::new();
+        for split in 0..AMOUNT_FOLDS {
+            for (file, same_person) in [("same.txt", true), ("diff.txt", false)] {
+                let path = format!("{protocol}/Split/FP/{:02}/{file}", split + 1);
+                for (idx_frontal, idx_profile) in read_split(&path)? {
+                    pairs.push((
+                        split,
+                        same_person,
+                        image(&frontal, idx_frontal)?,
+                        image(&profile, idx_profile)?,
+                    ));
+                }
+            }
+        }
+
+        Ok(Self { pairs })
+    }
+}
+
+impl Dataset for Cfp {
+    fn pairs(&self) -> Vec<(bool, String, String)> {
+        self.pairs
+            .iter()
+            .map(|(_, same_person, path1, path2)| (*same_person, path1.clone(), path2.clone()))
+            .collect()
+    }
+
+    fn fold(&self, idx: usize) -> usize {
+        self.pairs[idx].0
+    }
+
+    /// Images are stored as `<identity>/[frontal|profile]/<nr>.jpg`.
+    fn identity(&self, path: &str) -> String {
+        Path::new(path)
+            .parent()
+            .and_then(Path::parent)
+            .and_then(Path::file_name)
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    fn name(&self) -> String {
+        "cfp-fp".into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a CFP protocol folder in which split `i` of FP has one genuine pair (frontal `i`,
+    /// profile `i`) and one impostor pair. The FF splits must not be used.
+    fn protocol(name: &str) -> String {
+        let basepath = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        let protocol = basepath.join("Protocol");
+        let _ = std::fs::remove_dir_all(&basepath);
+        let list = |kind: &str| {
+            (1..=AMOUNT_FOLDS)
+                .map(|idx| format!("{idx} ../Data/Images/{idx:03}/{kind}/01.jpg\n"))
+                .collect::<String>()
+        };
+        std::fs::create_dir_all(&protocol).unwrap();
+        std::fs::write(protocol.join("Pair_list_F.txt"), list("frontal")).unwrap();
+        std::fs::write(protocol.join("Pair_list_P.txt"), list("profile") + "\n").unwrap();
+        for split in 1..=AMOUNT_FOLDS {
+            let other = split % AMOUNT_FOLDS + 1;
+            for (protocol_name, same, diff) in [
+                (
+                    "FP",
+                    format!("{split},{split}\n"),
+                    format!("{split}, {other}\n"),
+                ),
+                ("FF", format!("{other},{other}\n"), String::new()),
+            ] {
+                let dir = protocol.join(format!("Split/{protocol_name}/{split:02}"));
+                std::fs::create_dir_all(&dir).unwrap();
+                std::fs::write(dir.join("same.txt"), same).unwrap();
+                std::fs::write(dir.join("diff.txt"), diff).unwrap();
+            }
+        }
+        basepath.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn frontal_profile_splits() {
+        let basepath = protocol("reducedemb-cfp");
+        let cfp = Cfp::new(basepath.clone()).unwrap();
+        let pairs = cfp.pairs();
+        assert_eq!(pairs.len(), 2 * AMOUNT_FOLDS);
+
+        let image = |idx: usize, kind: &str| {
+            format!("{basepath}/Protocol/../Data/Images/{idx:03}/{kind}/01.jpg")
+        };
+        for split in 0..AMOUNT_FOLDS {
+            let other = (split + 1) % AMOUNT_FOLDS + 1;
+            let (same, diff) = (&pairs[2 * split], &pairs[2 * split + 1]);
+            assert_eq!(
+                *same,
+                (
+                    true,
+                    image(split + 1, "frontal"),
+                    image(split + 1, "profile")
+                )
+            );
+            assert_eq!(
+                *diff,
+                (false, image(split + 1, "frontal"), image(other, "profile"))
+            );
+            assert_eq!(cfp.fold(2 * split), split);
+            assert_eq!(cfp.fold(2 * split + 1), split);
+            assert_eq!(cfp.identity(&same.1), cfp.identity(&same.2));
+            assert_ne!(cfp.identity(&diff.1), cfp.identity(&diff.2));
+        }
+        assert_eq!(cfp.identity(&image(3, "profile")), "003");
+
+        std::fs::remove_dir_all(basepath).unwrap();
+    }
+
+    #[test]
+    fn malformed_protocol() {
+        let basepath = protocol("reducedemb-cfp-malformed");
+        let split = format!("{basepath}/Protocol/Split/FP/04/diff.txt");
+
+        std::fs::write(&split, "4,11\n").unwrap();
+        assert!(matches!(
+            Cfp::new(basepath.clone()),
+            Err(Error::UnknownImage(11))
+        ));
+        std::fs::write(&split, "0,1\n").unwrap();
+        assert!(matches!(
+            Cfp::new(basepath.clone()),
+            Err(Error::UnknownImage(0))
+        ));
+        std::fs::write(&split, "4;5\n").unwrap();
+        assert!(
+            matches!(Cfp::new(basepath.clone()), Err(Error::InvalidLine(line)) if line == "4;5")
+        );
+
+        std::fs::remove_file(&split).unwrap();
+        assert!(matches!(Cfp::new(basepath.clone()), Err(Error::IoError(_))));
+
+        std::fs::write(
+            format!("{basepath}/Protocol/Pair_list_F.txt"),
+            "1 a b.jpg\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            Cfp::new(basepath.clone()),
+            Err(Error::InvalidLine(_))
+        ));
+
+        std::fs::remove_dir_all(basepath).unwrap();
+    }
+}
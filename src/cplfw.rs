@@ -15,20 +15,52 @@
 // See the Licence for the specific language governing permissions and
 // limitations under the Licence.
 
-use crate::Dataset;
+use std::path::Path;
 
-/// Represents an image pair of the CPLFW dataset
+use crate::{Dataset, AMOUNT_FOLDS};
+
+#[derive(Debug)]
+/// All possible Cplfw Errors
+pub enum Error {
+    //Can't find pairs file
+    CsvError(csv::Error),
+}
+
+impl From<csv::Error> for Error {
+    fn from(value: csv::Error) -> Self {
+        Self::CsvError(value)
+    }
+}
+
+/// Represents an image pair of the CPLFW dataset. CALFW uses the same layout.
 pub struct Cplfw {
     pair: Vec<(bool, String, String)>,
+
+    /// Fold of each pair
+    folds: Vec<usize>,
+
+    /// Name of the dataset, `cplfw` or `calfw`
+    name: &'static str,
 }
 
 impl Cplfw {
-    pub fn new(pairs_file: &str, basepath: String) -> Result<Self, ()> {
+    pub fn new(pairs_file: &str, basepath: String) -> Result<Self, Error> {
+        Self::load(pairs_file, basepath, "cplfw")
+    }
+
+    /// Loads the CALFW dataset, whose pairs file has the same layout as the one of CPLFW.
+    pub fn calfw(pairs_file: &str, basepath: String) -> Result<Self, Error> {
+        Self::load(pairs_file, basepath, "calfw")
+    }
+
+    /// Both images of a pair are on consecutive lines (`<image> <label>`), the label is `0` for
+    /// different people. Lines without numeric label (i.e. headers) are ignored.
+    fn load(pairs_file: &str, basepath: String, name: &'static str) -> Result<Self, Error> {
         let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
             .flexible(true)
             .delimiter(b'\t')
-            .from_path(pairs_file)
-            .unwrap();
+            .from_path(pairs_file)?;
 
         let mut pairs = Vec::new();
 
@@ -36,6 +68,9 @@ impl Cplfw {
         for record in reader.records() {
             let name: String = record.unwrap()[0].into();
             let splitted = name.split(" ").collect::<Vec<_>>();
+            let Some(Ok(_)) = splitted.get(1).map(|label| label.parse::<u32>()) else {
+                continue;
+            };
 
             match prev {
                 Some((same, ref name)) => {
@@ -48,43 +83,101 @@ impl Cplfw {
                     prev = None;
                 }
                 None => {
-                    prev = Some((splitted[1] == "1", splitted[0].into()));
+                    prev = Some((splitted[1] != "0", splitted[0].into()));
                 }
             }
         }
 
-        Ok(Self { pair: pairs })
+        // The genuine and impostor pairs are each split into `AMOUNT_FOLDS` consecutive blocks.
+        // Numbering the pairs per label gives the same folds for files listing all genuine pairs
+        // first (CPLFW) and files alternating between genuine and impostor blocks.
+        let amount_same = pairs.iter().filter(|(same, _, _)| *same).count();
+        let amount = [pairs.len() - amount_same, amount_same];
+        let mut seen = [0, 0];
+        let folds = pairs
+            .iter()
+            .map(|(same, _, _)| {
+                let label = *same as usize;
+                seen[label] += 1;
+                (seen[label] - 1) * AMOUNT_FOLDS / amount[label]
+            })
+            .collect();
+
+        Ok(Self {
+            pair: pairs,
+            folds,
+            name,
+        })
     }
 }
 
 impl Dataset for Cplfw {
-    fn embeddings(&self, rec: &mut crate::arcface::Recognition) -> Vec<(bool, Vec<f32>, Vec<f32>)> {
-        self.pair
-            .iter()
-            .filter_map(|(same_person, path1, path2)| {
-                if let Some(emb1) = rec.get(path1.into()) {
-                    if let Some(emb2) = rec.get(path2.into()) {
-                        Some((*same_person, emb1.clone(), emb2.clone()))
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            })
-            .collect()
+    fn pairs(&self) -> Vec<(bool, String, String)> {
+        self.pair.clone()
+    }
+
+    /// Fold `i` consists of block `i` of the genuine and block `i` of the impostor pairs.
+    fn fold(&self, idx: usize) -> usize {
+        self.folds[idx]
     }
 
-    fn images(&self) -> Vec<String> {
-        let mut ret = Vec::new();
-        for (_, path1, path2) in &self.pair {
-            ret.push(path1.clone());
-            ret.push(path2.clone());
+    /// All images are stored in a single directory, named `<identity>_<nr>.jpg`.
+    fn identity(&self, path: &str) -> String {
+        let stem = Path::new(path)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        match stem.rsplit_once('_') {
+            Some((identity, _)) => identity.into(),
+            None => stem.into(),
         }
-        ret
     }
 
     fn name(&self) -> String {
-        "cplfw".into()
+        self.name.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a pairs file of `blocks` consecutive blocks of `(label, amount of pairs)`.
+    fn pairs_file(name: &str, blocks: &[(u8, usize)]) -> String {
+        let mut lines = String::new();
+        for (block, (label, amount)) in blocks.iter().enumerate() {
+            for pair in 0..*amount {
+                lines +=
+                    &format!("a_{block}_{pair}_1.jpg {label}\nb_{block}_{pair}_2.jpg {label}\n");
+            }
+        }
+        let path =
+            std::env::temp_dir().join(format!("reducedemb-{name}-{}.txt", std::process::id()));
+        std::fs::write(&path, lines).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn folds(data: &Cplfw) -> Vec<(bool, usize)> {
+        let pairs = data.pairs();
+        (0..pairs.len())
+            .map(|idx| (pairs[idx].0, data.fold(idx)))
+            .collect()
+    }
+
+    #[test]
+    fn folds_per_label() {
+        let halves = pairs_file("halves", &[(1, 30), (0, 30)]);
+        let data = Cplfw::new(&halves, "x".into()).unwrap();
+        std::fs::remove_file(halves).unwrap();
+        let expected: Vec<(bool, usize)> = (0..60).map(|idx| (idx < 30, idx % 30 / 3)).collect();
+        assert_eq!(folds(&data), expected);
+
+        let alternating: Vec<(u8, usize)> =
+            (0..AMOUNT_FOLDS).flat_map(|_| [(1, 3), (0, 3)]).collect();
+        let alternating = pairs_file("alternating", &alternating);
+        let data = Cplfw::calfw(&alternating, "x".into()).unwrap();
+        std::fs::remove_file(alternating).unwrap();
+        let expected: Vec<(bool, usize)> = (0..60).map(|idx| (idx % 6 < 3, idx / 6)).collect();
+        assert_eq!(folds(&data), expected);
     }
 }
@@ -0,0 +1,180 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+use std::str::FromStr;
+
+use face::{
+    detection::{retinaface::Retinaface, Face},
+    fast::FastInference,
+};
+use image::{DynamicImage, GenericImageView};
+
+use crate::arcface::Error;
+
+/// Minimum confidence of faces detected by FastDet
+const FASTDET_THRESHOLD: f32 = 0.7;
+
+/// Common interface of all face detectors.
+pub trait FaceDetector {
+    /// Detects all faces in `img`.
+    fn detect(&self, img: &DynamicImage) -> Result<Vec<Face>, Error>;
+}
+
+impl FaceDetector for Retinaface {
+    fn detect(&self, img: &DynamicImage) -> Result<Vec<Face>, Error> {
+        Ok(self.inference(img)?)
+    }
+}
+
+impl FaceDetector for FastInference {
+    fn detect(&self, img: &DynamicImage) -> Result<Vec<Face>, Error> {
+        Ok(self.inference(img)?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// All available face detectors.
+pub enum Detector {
+    /// RetinaFace for 250x250 images (TF Lite)
+    Retinaface,
+
+    /// FastDet with 320x320 input (ONNX)
+    Fastdet320,
+
+    /// FastDet with 640x640 input (ONNX)
+    Fastdet640,
+}
+
+impl Detector {
+    /// Name of the detector, used to keep the caches of different detectors apart.
+    pub fn name(&self) -> &'static str {
+        match self {
+            // Name of the original (RetinaFace-only) caches
+            Detector::Retinaface => "250x250",
+            Detector::Fastdet320 => "fastdet320",
+            Detector::Fastdet640 => "fastdet640",
+        }
+    }
+
+    /// Loads the models of the detector.
+    ///
+    /// # Errors
+    /// - `Error::RetinafaceError` or `Error::FastdetError` returned, if the detector could not be
+    ///   created, e.g. because models could not be found
+    pub fn create(&self) -> Result<Box<dyn FaceDetector>, Error> {
+        Ok(match self {
+            Detector::Retinaface => Box::new(Retinaface::new(
+                "data/models/retinaface-250x250.tflite",
+                "data/models/retinaface-anchors-250x250.json",
+            )?),
+            Detector::Fastdet320 => Box::new(FastInference::new(
+                "data/models/fastdet/fastdet_320.onnx",
+                FASTDET_THRESHOLD,
+            )?),
+            Detector::Fastdet640 => Box::new(FastInference::new(
+                "data/models/fastdet/fastdet_640.onnx",
+                FASTDET_THRESHOLD,
+            )?),
+        })
+    }
+}
+
+impl FromStr for Detector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "retinaface" => Ok(Detector::Retinaface),
+            "fastdet320" => Ok(Detector::Fastdet320),
+            "fastdet640" => Ok(Detector::Fastdet640),
+            _ => Err(format!(
+                "Unknown detector {s}, possible values: retinaface, fastdet320, fastdet640"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Defines which face is used if multiple faces are detected in an image.
+pub enum FacePolicy {
+    /// Face whose nose is closest to the center of the image
+    MostCentered,
+
+    /// Face with the largest bounding box
+    Largest,
+
+    /// Face with the highest detection confidence
+    MostConfident,
+
+    /// Only images with exactly one face are used
+    Single,
+}
+
+impl FacePolicy {
+    /// Name of the policy, stored alongside each cached embedding.
+    pub fn name(&self) -> &'static str {
+        match self {
+            FacePolicy::MostCentered => "most-centered",
+            FacePolicy::Largest => "largest",
+            FacePolicy::MostConfident => "most-confident",
+            FacePolicy::Single => "single",
+        }
+    }
+
+    /// Returns the index of the face of `faces` (detected in `img`) to use, or `None` if no face
+    /// should be used.
+    pub fn select(&self, faces: &[Face], img: &DynamicImage) -> Option<usize> {
+        let (width, height) = img.dimensions();
+        let (center_x, center_y) = (width as f32 / 2., height as f32 / 2.);
+
+        let key = |face: &Face| match self {
+            // Smaller is better for all keys
+            FacePolicy::MostCentered => {
+                (center_x - face.landmarks.nose.x).abs() + (center_y - face.landmarks.nose.y).abs()
+            }
+            FacePolicy::Largest => -(face.rect.width * face.rect.height),
+            FacePolicy::MostConfident => -face.confidence,
+            FacePolicy::Single => 0.,
+        };
+
+        if *self == FacePolicy::Single && faces.len() != 1 {
+            return None;
+        }
+        faces
+            .iter()
+            .map(key)
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(index, _)| index)
+    }
+}
+
+impl FromStr for FacePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "most-centered" => Ok(FacePolicy::MostCentered),
+            "largest" => Ok(FacePolicy::Largest),
+            "most-confident" => Ok(FacePolicy::MostConfident),
+            "single" => Ok(FacePolicy::Single),
+            _ => Err(format!(
+                "Unknown face policy {s}, possible values: most-centered, largest, most-confident, single"
+            )),
+        }
+    }
+}
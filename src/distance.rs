@@ -0,0 +1,168 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Metric used to compare two embeddings. Smaller values always mean more similar embeddings, so
+/// that the same threshold logic can be used for all of them.
+pub enum Distance {
+    /// Squared euclidean distance
+    L2,
+
+    /// 1 - cosine similarity
+    Cosine,
+
+    /// Manhattan distance
+    L1,
+
+    /// Negative inner product
+    InnerProduct,
+
+    /// Amount of differing signs, i.e. hamming distance of the binarized embeddings
+    Hamming,
+}
+
+impl Distance {
+    /// Calculates the distance between `emb1` and `emb2`. Both have to be of the same length.
+    pub fn calc(&self, emb1: &[f32], emb2: &[f32]) -> f32 {
+        self.calc_pairs(emb1.iter().copied().zip(emb2.iter().copied()))
+    }
+
+    /// Same as `calc`, but only uses the dimensions listed in `indices`, without copying them.
+    pub fn calc_indexed(&self, emb1: &[f32], emb2: &[f32], indices: &[usize]) -> f32 {
+        self.calc_pairs(indices.iter().map(|&i| (emb1[i], emb2[i])))
+    }
+
+    fn calc_pairs(&self, pairs: impl Iterator<Item = (f32, f32)>) -> f32 {
+        match self {
+            Distance::L2 => pairs.map(|(a, b)| (a - b) * (a - b)).sum(),
+            Distance::Cosine => {
+                let (dot, norm1, norm2) = pairs.fold((0., 0., 0.), |(dot, n1, n2), (a, b)| {
+                    (dot + a * b, n1 + a * a, n2 + b * b)
+                });
+                match norm1 == 0. || norm2 == 0. {
+                    true => 1.,
+                    false => 1. - dot / (norm1.sqrt() * norm2.sqrt()),
+                }
+            }
+            Distance::L1 => pairs.map(|(a, b)| (a - b).abs()).sum(),
+            Distance::InnerProduct => -pairs.map(|(a, b)| a * b).sum::<f32>(),
+            Distance::Hamming => pairs.filter(|(a, b)| (*a > 0.) != (*b > 0.)).count() as f32,
+        }
+    }
+
+    /// Same as `calc`, but for quantized embeddings. The sums are calculated exactly using integer
+    /// arithmetic, only the result is converted to `f64` (only `Distance::Cosine` isn't an
+    /// integer).
+    pub fn calc_int(&self, emb1: &[i32], emb2: &[i32]) -> f64 {
+        let pairs = emb1.iter().zip(emb2).map(|(a, b)| (*a as i64, *b as i64));
+        match self {
+            Distance::L2 => pairs.map(|(a, b)| (a - b) * (a - b)).sum::<i64>() as f64,
+            Distance::Cosine => {
+                let (dot, norm1, norm2) = pairs.fold((0, 0, 0), |(dot, n1, n2), (a, b)| {
+                    (dot + a * b, n1 + a * a, n2 + b * b)
+                });
+                match norm1 == 0 || norm2 == 0 {
+                    true => 1.,
+                    false => 1. - dot as f64 / ((norm1 as f64).sqrt() * (norm2 as f64).sqrt()),
+                }
+            }
+            Distance::L1 => pairs.map(|(a, b)| (a - b).abs()).sum::<i64>() as f64,
+            Distance::InnerProduct => -pairs.map(|(a, b)| a * b).sum::<i64>() as f64,
+            Distance::Hamming => pairs.filter(|(a, b)| (*a > 0) != (*b > 0)).count() as f64,
+        }
+    }
+}
+
+/// Scales `emb` to unit length. ArcFace embeddings are unit length at full size, but lose this
+/// property when dimensions are removed.
+pub fn normalize(mut emb: Vec<f32>) -> Vec<f32> {
+    let norm = emb.iter().map(|a| a * a).sum::<f32>().sqrt();
+    if norm > 0. {
+        emb.iter_mut().for_each(|a| *a /= norm);
+    }
+    emb
+}
+
+impl FromStr for Distance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "l2" => Ok(Distance::L2),
+            "cosine" => Ok(Distance::Cosine),
+            "l1" => Ok(Distance::L1),
+            "inner-product" => Ok(Distance::InnerProduct),
+            "hamming" => Ok(Distance::Hamming),
+            _ => Err(format!(
+                "Unknown metric {s}, possible values: l2, cosine, l1, inner-product, hamming"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Distance; 5] = [
+        Distance::L2,
+        Distance::Cosine,
+        Distance::L1,
+        Distance::InnerProduct,
+        Distance::Hamming,
+    ];
+
+    #[test]
+    fn values() {
+        let (a, b) = ([1., 2., -1.], [3., 0., 1.]);
+        assert_eq!(Distance::L2.calc(&a, &b), 12.);
+        assert_eq!(Distance::L1.calc(&a, &b), 6.);
+        assert_eq!(Distance::InnerProduct.calc(&a, &b), -2.);
+        assert_eq!(Distance::Hamming.calc(&a, &b), 2.);
+        assert!(Distance::Cosine.calc(&a, &a).abs() < 1e-6);
+        assert_eq!(Distance::Cosine.calc(&[1., 0.], &[0., 1.]), 1.);
+    }
+
+    #[test]
+    fn cosine_of_zero_vector() {
+        assert_eq!(Distance::Cosine.calc(&[0., 0.], &[1., 2.]), 1.);
+        assert_eq!(Distance::Cosine.calc_int(&[0, 0], &[1, 2]), 1.);
+    }
+
+    #[test]
+    fn indexed_and_int_match_calc() {
+        let emb1 = [0.5, -1.5, 2., 7., -3.];
+        let emb2 = [1., 2., -2., 4., -3.];
+        let indices = [4, 0, 2];
+        let selected = |emb: &[f32]| indices.iter().map(|&i| emb[i]).collect::<Vec<_>>();
+        let int = |emb: &[f32]| emb.iter().map(|x| (x * 2.) as i32).collect::<Vec<_>>();
+        let scaled = |emb: &[f32]| emb.iter().map(|x| x * 2.).collect::<Vec<_>>();
+        for metric in ALL {
+            assert_eq!(
+                metric.calc_indexed(&emb1, &emb2, &indices),
+                metric.calc(&selected(&emb1), &selected(&emb2))
+            );
+            let expected = metric.calc(&scaled(&emb1), &scaled(&emb2)) as f64;
+            assert!((metric.calc_int(&int(&emb1), &int(&emb2)) - expected).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,637 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+use crate::arcface::Recognition;
+use crate::distance::{normalize, Distance};
+use crate::misc::ConfusionMatrix;
+use crate::profile::{DType, ReductionProfile};
+use crate::roc::Roc;
+use crate::{Dataset, Fold};
+use itertools::Itertools;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug)]
+/// All possible errors of an evaluation.
+pub enum Error {
+    /// `Pairing::AllVsAll` results in a single fold, so it can't be used with `Protocol::TenFold`
+    AllVsAllWithTenFold,
+}
+
+/// Computes confusion matrix for a given set of distances.
+pub struct Result<T>
+where
+    T: PartialOrd + Clone,
+{
+    pub same: Vec<T>,
+    pub diff: Vec<T>,
+
+    /// Amount of pairs of the same person rejected without distance (`MissingFace::FalseReject`)
+    pub rejected_same: usize,
+
+    /// Amount of pairs of different people rejected without distance
+    pub rejected_diff: usize,
+}
+
+impl<T: PartialOrd + Clone + std::fmt::Display + Copy + std::fmt::Debug> Default for Result<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: PartialOrd + Clone + std::fmt::Display + Copy + std::fmt::Debug> Result<T> {
+    pub fn new() -> Self {
+        Self {
+            same: Vec::new(),
+            diff: Vec::new(),
+            rejected_same: 0,
+            rejected_diff: 0,
+        }
+    }
+
+    /// Adds the calculated distance of two images of the same person.
+    pub fn add_same(&mut self, dist: T) {
+        self.same.push(dist);
+    }
+
+    /// Adds the calculated distance of two images of different people.
+    pub fn add_diff(&mut self, dist: T) {
+        self.diff.push(dist);
+    }
+
+    /// Adds a pair which is rejected without calculating a distance, e.g. because of a missing
+    /// face.
+    pub fn reject(&mut self, same_person: bool) {
+        match same_person {
+            true => self.rejected_same += 1,
+            false => self.rejected_diff += 1,
+        }
+    }
+
+    /// Amount of pairs of the same person, incl. rejected ones.
+    pub fn amount_same(&self) -> usize {
+        self.same.len() + self.rejected_same
+    }
+
+    /// Amount of all pairs, incl. rejected ones.
+    pub fn amount(&self) -> usize {
+        self.amount_same() + self.diff.len() + self.rejected_diff
+    }
+
+    pub fn calc(&self) -> String {
+        let (best_threshold, confusion_matrix) = self.get_confusion_matrix();
+
+        let best_amount_fp = confusion_matrix.fp;
+        let best_amount_fn = confusion_matrix.fne;
+
+        format!("{best_threshold};{best_amount_fp};{best_amount_fn}")
+    }
+
+    pub fn get_confusion_matrix(&self) -> (T, ConfusionMatrix) {
+        let (threshold, mut conf) = ConfusionMatrix::best(&self.same, &self.diff);
+        conf.reject(self.rejected_same, self.rejected_diff);
+        (threshold, conf)
+    }
+
+    pub fn calc_rel(&self) -> String {
+        let (best_threshold, conf) = self.get_confusion_matrix();
+
+        format!(
+            "{best_threshold};{};{}",
+            conf.false_discovery_rate(),
+            conf.false_omission_rate()
+        )
+    }
+
+    pub fn roc(&self) -> Roc<T> {
+        Roc::with_rejected(
+            &self.same,
+            &self.diff,
+            self.rejected_same,
+            self.rejected_diff,
+        )
+    }
+
+    pub fn calc_return_false(&self) -> i32 {
+        let (_, confusion_matrix) = self.get_confusion_matrix();
+        confusion_matrix.amount_false()
+    }
+}
+
+/// Defines how the threshold is chosen and how the results are reported.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Protocol {
+    /// Threshold is chosen on all pairs and evaluated on the very same pairs.
+    Full,
+
+    /// Official protocol: threshold is learned on 9 folds and evaluated on the held-out fold.
+    TenFold,
+}
+
+impl Protocol {
+    /// Column names of the values returned by `Result::calc` or `Folds::accuracy`.
+    pub fn header(&self) -> &'static str {
+        match self {
+            Protocol::Full => "optimal_threshold_used;fp;fn",
+            Protocol::TenFold => "mean_accuracy;std_accuracy",
+        }
+    }
+}
+
+/// Defines which images are compared with each other.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pairing {
+    /// Pairs of the dataset (e.g. of its pairs file)
+    Pairs,
+
+    /// Every image of the dataset is compared with every other image, using `Dataset::identity`
+    /// to tell genuine from impostor comparisons. Results in a single fold, so it can only be
+    /// used with `Protocol::Full`.
+    AllVsAll {
+        /// If set, all genuine comparisons but only this amount of randomly drawn impostor
+        /// comparisons are used. Each pair of images is drawn at most once, so there are less
+        /// impostor comparisons if the dataset doesn't have enough of them
+        impostors: Option<usize>,
+
+        /// Seed of the random generator drawing the impostor comparisons
+        seed: u64,
+    },
+}
+
+/// Settings shared by all evaluation actions.
+pub struct Evaluation {
+    pub protocol: Protocol,
+
+    /// Defines which images are compared
+    pub pairing: Pairing,
+
+    /// False accept rates at which the true accept rate is reported additionally
+    pub fars: Vec<f32>,
+
+    /// Metric used to compare two embeddings
+    pub metric: Distance,
+
+    /// If set, reduced embeddings are additionally evaluated after L2-normalizing them
+    pub normalize: bool,
+}
+
+impl Evaluation {
+    /// Checks if `pairing` can be used with `protocol`.
+    ///
+    /// # Errors
+    /// - `Error::AllVsAllWithTenFold` returned, if `Pairing::AllVsAll` is used with
+    ///   `Protocol::TenFold`
+    pub fn validate(&self) -> std::result::Result<(), Error> {
+        match (self.pairing, self.protocol) {
+            (Pairing::AllVsAll { .. }, Protocol::TenFold) => Err(Error::AllVsAllWithTenFold),
+            _ => Ok(()),
+        }
+    }
+
+    /// Column names of the values returned by `Folds::calc`.
+    pub fn header(&self) -> String {
+        format!("{}{}", self.protocol.header(), self.tar_header())
+    }
+
+    /// Variants of reduced embeddings to evaluate: `false` for the reduced embedding as-is, `true`
+    /// for the L2-normalized reduced embedding.
+    pub fn variants(&self) -> Vec<bool> {
+        match self.normalize {
+            true => vec![false, true],
+            false => vec![false],
+        }
+    }
+
+    /// Same as `header`, once for every variant returned by `variants`.
+    pub fn variants_header(&self) -> String {
+        self.variants()
+            .iter()
+            .map(|normalized| match normalized {
+                false => self.header(),
+                true => self
+                    .header()
+                    .split(';')
+                    .map(|column| format!("normalized_{column}"))
+                    .join(";"),
+            })
+            .join(";")
+    }
+
+    /// Column names of the values returned by `Folds::tars`.
+    pub fn tar_header(&self) -> String {
+        self.fars
+            .iter()
+            .map(|far| format!(";tar@far={far}"))
+            .collect()
+    }
+}
+
+/// Holds one `Result` per fold of the used `Protocol`.
+pub struct Folds<'a, T>
+where
+    T: PartialOrd + Clone,
+{
+    pub eval: &'a Evaluation,
+    pub folds: Vec<Result<T>>,
+}
+
+impl<'a, T: PartialOrd + Clone + std::fmt::Display + Copy + std::fmt::Debug> Folds<'a, T> {
+    pub fn calc(&self) -> String {
+        let ret = match self.eval.protocol {
+            Protocol::Full => self.folds[0].calc(),
+            Protocol::TenFold => {
+                let (mean, std) = self.accuracy();
+                format!("{mean};{std}")
+            }
+        };
+        ret + &self.tars()
+    }
+
+    pub fn calc_rel(&self) -> String {
+        match self.eval.protocol {
+            Protocol::Full => self.folds[0].calc_rel() + &self.tars(),
+            Protocol::TenFold => self.calc(),
+        }
+    }
+
+    /// True accept rates at all configured false accept rates, each prefixed by `;`.
+    pub fn tars(&self) -> String {
+        if self.eval.fars.is_empty() {
+            return String::new();
+        }
+
+        match self.eval.protocol {
+            Protocol::Full => {
+                let roc = self.folds[0].roc();
+                self.eval
+                    .fars
+                    .iter()
+                    .map(|far| format!(";{}", roc.at_far(*far).map_or(0., |p| p.tpr)))
+                    .collect()
+            }
+            Protocol::TenFold => {
+                // Learn threshold on 9 folds, apply it on the held-out fold
+                let mut tars = vec![0.; self.eval.fars.len()];
+                for (idx, test) in self.folds.iter().enumerate() {
+                    let roc = self.merged(Some(idx)).roc();
+                    for (tar, far) in tars.iter_mut().zip(self.eval.fars.iter()) {
+                        if let Some(p) = roc.at_far(*far) {
+                            let accepted = test.same.iter().filter(|d| **d <= p.threshold).count();
+                            *tar += accepted as f32 / test.amount_same() as f32;
+                        }
+                    }
+                }
+                tars.iter()
+                    .map(|tar| format!(";{}", tar / self.folds.len() as f32))
+                    .collect()
+            }
+        }
+    }
+
+    /// Merges all folds, except the one with index `skip`.
+    pub fn merged(&self, skip: Option<usize>) -> Result<T> {
+        let mut ret = Result::new();
+        for (_, fold) in self
+            .folds
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| Some(*i) != skip)
+        {
+            ret.same.extend(fold.same.iter().copied());
+            ret.diff.extend(fold.diff.iter().copied());
+            ret.rejected_same += fold.rejected_same;
+            ret.rejected_diff += fold.rejected_diff;
+        }
+        ret
+    }
+
+    /// Mean and standard deviation of the accuracy on the held-out folds.
+    pub fn accuracy(&self) -> (f32, f32) {
+        let accuracies: Vec<f32> = (0..self.folds.len())
+            .map(|test| {
+                let (threshold, _) = self.merged(Some(test)).get_confusion_matrix();
+
+                let test = &self.folds[test];
+                let mut conf = ConfusionMatrix::new(threshold, &test.same, &test.diff);
+                conf.reject(test.rejected_same, test.rejected_diff);
+                conf.amount_pos() as f32 / test.amount() as f32
+            })
+            .collect();
+
+        let mean = accuracies.iter().sum::<f32>() / accuracies.len() as f32;
+        let var = accuracies
+            .iter()
+            .map(|a| (a - mean) * (a - mean))
+            .sum::<f32>()
+            / accuracies.len() as f32;
+        (mean, var.sqrt())
+    }
+}
+
+/// Calculates the distance of every pair of `data` using `dist`, split according to the protocol
+/// of `eval`. Pairs without embeddings (`MissingFace::FalseReject`) are rejected.
+///
+/// # Errors
+/// - `Error::AllVsAllWithTenFold` returned, if `Pairing::AllVsAll` is used with
+///   `Protocol::TenFold`
+pub fn evaluate<'a, T, F>(
+    data: &dyn Dataset,
+    rec: &mut Recognition,
+    eval: &'a Evaluation,
+    mut dist: F,
+) -> std::result::Result<Folds<'a, T>, Error>
+where
+    T: PartialOrd + Clone + std::fmt::Display + Copy + std::fmt::Debug,
+    F: FnMut(&[f32], &[f32]) -> T,
+{
+    eval.validate()?;
+    if let Pairing::AllVsAll { impostors, seed } = eval.pairing {
+        let folds = vec![all_vs_all(data, rec, impostors, seed, dist)];
+        return Ok(Folds { eval, folds });
+    }
+
+    let pairs = match eval.protocol {
+        Protocol::Full => vec![data.embeddings(rec)],
+        Protocol::TenFold => data.folds(rec),
+    };
+    Ok(evaluate_folds(&pairs, eval, |_, emb1, emb2| {
+        dist(emb1, emb2)
+    }))
+}
+
+/// Same as `evaluate`, but on already split `pairs` (e.g. `Dataset::folds`), passing the index
+/// of the fold of each pair to `dist`. For `Protocol::Full`, all folds are merged.
+pub fn evaluate_folds<'a, T, F>(pairs: &[Fold], eval: &'a Evaluation, mut dist: F) -> Folds<'a, T>
+where
+    T: PartialOrd + Clone + std::fmt::Display + Copy + std::fmt::Debug,
+    F: FnMut(usize, &[f32], &[f32]) -> T,
+{
+    let folds = pairs
+        .iter()
+        .enumerate()
+        .map(|(idx, fold)| {
+            let mut result = Result::new();
+            for (same_person, emb1, emb2) in fold {
+                if emb1.is_empty() || emb2.is_empty() {
+                    result.reject(*same_person);
+                    continue;
+                }
+                let dist = dist(idx, emb1, emb2);
+                if *same_person {
+                    result.add_same(dist);
+                } else {
+                    result.add_diff(dist);
+                }
+            }
+            result
+        })
+        .collect();
+
+    let ret = Folds { eval, folds };
+    match eval.protocol {
+        Protocol::Full if ret.folds.len() > 1 => Folds {
+            eval,
+            folds: vec![ret.merged(None)],
+        },
+        _ => ret,
+    }
+}
+
+/// Compares all images of `data` with each other (see `Pairing::AllVsAll`). Images without
+/// embedding are ignored.
+fn all_vs_all<T, F>(
+    data: &dyn Dataset,
+    rec: &mut Recognition,
+    impostors: Option<usize>,
+    seed: u64,
+    dist: F,
+) -> Result<T>
+where
+    T: PartialOrd + Clone + std::fmt::Display + Copy + std::fmt::Debug,
+    F: FnMut(&[f32], &[f32]) -> T,
+{
+    let mut identities = HashMap::new();
+    let mut embeddings = Vec::new();
+    for image in data.images() {
+        if let Some(emb) = rec.embedding(&image) {
+            let next_id = identities.len();
+            let id = *identities.entry(data.identity(&image)).or_insert(next_id);
+            embeddings.push((id, emb));
+        }
+    }
+    compare_all(&embeddings, impostors, seed, dist)
+}
+
+/// Compares all `(identity, embedding)` pairs of `embeddings` with each other, see `all_vs_all`.
+/// Without at least two identities, no impostor comparisons are drawn.
+fn compare_all<T, F>(
+    embeddings: &[(usize, Vec<f32>)],
+    impostors: Option<usize>,
+    seed: u64,
+    mut dist: F,
+) -> Result<T>
+where
+    T: PartialOrd + Clone + std::fmt::Display + Copy + std::fmt::Debug,
+    F: FnMut(&[f32], &[f32]) -> T,
+{
+    let identities: HashSet<usize> = embeddings.iter().map(|(id, _)| *id).collect();
+    let mut result = Result::new();
+    for (idx, (id1, emb1)) in embeddings.iter().enumerate() {
+        for (id2, emb2) in &embeddings[idx + 1..] {
+            if id1 == id2 {
+                result.add_same(dist(emb1, emb2));
+            } else if impostors.is_none() {
+                result.add_diff(dist(emb1, emb2));
+            }
+        }
+    }
+
+    // Impostors can only be drawn if there are at least two identities
+    if identities.len() < 2 {
+        return result;
+    }
+    if let Some(impostors) = impostors {
+        let mut rng = StdRng::seed_from_u64(seed);
+        // Each unordered pair of images is drawn at most once
+        let mut drawn = HashSet::new();
+        // If there are less impostor pairs than requested, the loop would never end
+        let mut attempts = 0;
+        while result.diff.len() < impostors && attempts < impostors * 100 {
+            attempts += 1;
+            let idx1 = rng.gen_range(0..embeddings.len());
+            let idx2 = rng.gen_range(0..embeddings.len());
+            let ((id1, emb1), (id2, emb2)) = (&embeddings[idx1], &embeddings[idx2]);
+            if id1 != id2 && drawn.insert((idx1.min(idx2), idx1.max(idx2))) {
+                result.add_diff(dist(emb1, emb2));
+            }
+        }
+    }
+    result
+}
+
+/// Evaluates `profile` on all pairs of `data`. If `normalized` is set, the selected dimensions are
+/// L2-normalized before quantization. Quantized dimensions are compared using integer arithmetic
+/// (`Distance::calc_int`).
+///
+/// # Errors
+/// - Same as `evaluate`
+pub fn evaluate_profile<'a>(
+    data: &dyn Dataset,
+    rec: &mut Recognition,
+    eval: &'a Evaluation,
+    profile: &ReductionProfile,
+    normalized: bool,
+) -> std::result::Result<Folds<'a, f64>, Error> {
+    evaluate(data, rec, eval, |emb1, emb2| {
+        let emb1 = profile.quantize(variant(profile.select(emb1), normalized));
+        let emb2 = profile.quantize(variant(profile.select(emb2), normalized));
+        match profile.dtype {
+            DType::F32 => eval.metric.calc(&emb1, &emb2) as f64,
+            // Quantized values are integers in the range of `i32`, so the conversion is exact
+            _ => {
+                let emb1: Vec<i32> = emb1.iter().map(|&x| x as i32).collect();
+                let emb2: Vec<i32> = emb2.iter().map(|&x| x as i32).collect();
+                eval.metric.calc_int(&emb1, &emb2)
+            }
+        }
+    })
+}
+
+/// Returns `emb`, L2-normalized if `normalized` is set.
+pub fn variant(emb: Vec<f32>, normalized: bool) -> Vec<f32> {
+    match normalized {
+        true => normalize(emb),
+        false => emb,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluation(protocol: Protocol, fars: Vec<f32>) -> Evaluation {
+        Evaluation {
+            protocol,
+            pairing: Pairing::Pairs,
+            fars,
+            metric: Distance::L2,
+            normalize: false,
+        }
+    }
+
+    /// Three folds, each with a single genuine and impostor distance. Every fold on its own is
+    /// separable, but the thresholds learned on the other folds are not always.
+    fn folds(eval: &Evaluation) -> Folds<f32> {
+        let folds = [(0.1, 0.5), (0.2, 0.3), (0.4, 0.6)]
+            .into_iter()
+            .map(|(same, diff)| {
+                let mut result = Result::new();
+                result.add_same(same);
+                result.add_diff(diff);
+                result
+            })
+            .collect();
+        Folds { eval, folds }
+    }
+
+    #[test]
+    fn accuracy_uses_held_out_threshold() {
+        let eval = evaluation(Protocol::TenFold, vec![]);
+        // Thresholds 0.2, 0.4 and 0.2 result in accuracies 1, 0.5 and 0.5
+        let (mean, std) = folds(&eval).accuracy();
+        assert!((mean - 2. / 3.).abs() < 1e-6);
+        assert!((std - (1_f32 / 18.).sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn tars_use_held_out_threshold() {
+        // Thresholds at a FAR of 0 learned on the other folds: 0.2, 0.4 and 0.2
+        let eval = evaluation(Protocol::TenFold, vec![0.]);
+        let tar: f32 = folds(&eval).tars()[1..].parse().unwrap();
+        assert!((tar - 2. / 3.).abs() < 1e-6);
+
+        // Threshold 0.2 on all pairs
+        let eval = evaluation(Protocol::Full, vec![0., 1.]);
+        let full = Folds {
+            eval: &eval,
+            folds: vec![folds(&eval).merged(None)],
+        };
+        let tars: Vec<f32> = full.tars()[1..]
+            .split(';')
+            .map(|t| t.parse().unwrap())
+            .collect();
+        assert!((tars[0] - 2. / 3.).abs() < 1e-6);
+        assert_eq!(tars[1], 1.);
+    }
+
+    #[test]
+    fn all_vs_all_with_ten_fold_is_rejected() {
+        let mut eval = evaluation(Protocol::TenFold, vec![]);
+        assert!(eval.validate().is_ok());
+        eval.pairing = Pairing::AllVsAll {
+            impostors: None,
+            seed: 0,
+        };
+        assert!(matches!(eval.validate(), Err(Error::AllVsAllWithTenFold)));
+        eval.protocol = Protocol::Full;
+        assert!(eval.validate().is_ok());
+    }
+
+    /// Images of three identities with 3, 2 and 1 images, i.e. 4 genuine and 11 impostor pairs.
+    /// The distance identifies the pair of images.
+    fn compare(impostors: Option<usize>, seed: u64) -> Result<f32> {
+        let embeddings: Vec<(usize, Vec<f32>)> = [0, 0, 0, 1, 1, 2]
+            .into_iter()
+            .enumerate()
+            .map(|(idx, id)| (id, vec![idx as f32]))
+            .collect();
+        compare_all(&embeddings, impostors, seed, |a, b| {
+            a[0].min(b[0]) * 10. + a[0].max(b[0])
+        })
+    }
+
+    #[test]
+    fn all_vs_all_comparisons() {
+        let all = compare(None, 0);
+        assert_eq!(all.same, [1., 2., 12., 34.]);
+        assert_eq!(all.diff.len(), 11);
+
+        let drawn = compare(Some(5), 1);
+        assert_eq!(drawn.same, all.same);
+        assert_eq!(drawn.diff.len(), 5);
+        assert!(drawn.diff.iter().all(|d| all.diff.contains(d)));
+        assert_eq!(drawn.diff, compare(Some(5), 1).diff);
+
+        // Each pair is drawn at most once, so there are at most 11 impostor comparisons
+        let mut drawn = compare(Some(20), 2).diff;
+        drawn.sort_by(|a, b| a.total_cmp(b));
+        let mut expected = all.diff.clone();
+        expected.sort_by(|a, b| a.total_cmp(b));
+        assert_eq!(drawn, expected);
+    }
+
+    #[test]
+    fn all_vs_all_single_identity() {
+        let embeddings = vec![(0, vec![0.]), (0, vec![1.])];
+        let result = compare_all(&embeddings, Some(10), 0, |a, b| a[0] - b[0]);
+        assert_eq!(result.same.len(), 1);
+        assert!(result.diff.is_empty());
+        assert!(compare_all(&[], Some(10), 0, |a, b| a[0] - b[0])
+            .same
+            .is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+//! Images without usable face and how they are handled during evaluation.
+
+use std::{path::Path, path::PathBuf, str::FromStr};
+
+use serde::Serialize;
+
+#[derive(Debug)]
+/// All possible errors when writing a failure report.
+pub enum Error {
+    /// Report could not be written
+    IoError(std::io::Error),
+
+    /// Report could not be serialized as JSON
+    SerializeError(serde_json::Error),
+
+    /// Report could not be serialized as CSV
+    CsvError(csv::Error),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Self::SerializeError(value)
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(value: csv::Error) -> Self {
+        Self::CsvError(value)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Reason why no embedding could be calculated for an image.
+pub enum Reason {
+    /// The detector did not find any face
+    NoFace,
+
+    /// Faces have been found, but the face selection policy rejected all of them (e.g. multiple
+    /// faces with `FacePolicy::Single`)
+    NotSelected,
+}
+
+#[derive(Serialize, Debug, Clone)]
+/// Single image without usable face.
+pub struct Failure {
+    pub path: PathBuf,
+    pub reason: Reason,
+
+    /// Amount of detected faces
+    pub faces: usize,
+}
+
+/// Writes `failures` to `path`. Files ending with `.json` are written as JSON, all other files as
+/// CSV (`path;reason;faces`).
+///
+/// # Errors
+/// - `Error::IoError` or `Error::CsvError` returned, if `path` can't be written
+pub fn write_report(path: &Path, failures: &[Failure]) -> Result<(), Error> {
+    if path.extension().is_some_and(|ext| ext == "json") {
+        std::fs::write(path, serde_json::to_string_pretty(failures)?)?;
+        return Ok(());
+    }
+
+    let mut writer = csv::WriterBuilder::new().delimiter(b';').from_path(path)?;
+    for failure in failures {
+        writer.serialize(failure)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Defines how pairs are evaluated if no embedding is available for (at least) one of its images.
+pub enum MissingFace {
+    /// The pair is ignored
+    Skip,
+
+    /// The pair is rejected, i.e. counted as false negative for the same person and as true
+    /// negative for different people
+    FalseReject,
+
+    /// The embedding is calculated of the center of the image, without face detection
+    CenterCrop,
+}
+
+impl FromStr for MissingFace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(MissingFace::Skip),
+            "false-reject" => Ok(MissingFace::FalseReject),
+            "center-crop" => Ok(MissingFace::CenterCrop),
+            _ => Err(format!(
+                "Unknown missing face policy {s}, possible values: skip, false-reject, center-crop"
+            )),
+        }
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use itertools::Itertools;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{Dataset, IsSamePerson, AMOUNT_FOLDS};
+
+/// File extensions of images considered by `Folders`
+const IMAGE_EXTENSIONS: [&str; 3] = ["jpg", "jpeg", "png"];
+
+/// Maximum amount of random draws per requested impostor pair, before giving up
+const IMPOSTOR_ATTEMPTS: usize = 100;
+
+#[derive(Debug)]
+/// All possible Folders Errors
+pub enum Error {
+    /// Directory could not be scanned or pairs file could not be written
+    IoError(std::io::Error),
+
+    /// Less than two identities with at least two images each have been found
+    NotEnoughImages,
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+/// Settings of the pair generation of `Folders`.
+pub struct PairGeneration {
+    /// Amount of pairs, half of them genuine (same person) and half impostor (different people)
+    pub amount: usize,
+
+    /// Maximum amount of genuine and impostor pairs (each) a single identity is part of
+    pub max_per_identity: usize,
+
+    /// Seed of the random generator, the same seed always results in the same pairs
+    pub seed: u64,
+}
+
+impl Default for PairGeneration {
+    /// Same amount of pairs as LFW.
+    fn default() -> Self {
+        Self {
+            amount: 6000,
+            max_per_identity: 10,
+            seed: 0,
+        }
+    }
+}
+
+/// Dataset consisting of a directory per identity (`<basepath>/<identity>/<image>`), without a
+/// pairs file. Balanced genuine and impostor pairs are generated deterministically.
+///
+/// Genuine and impostor pairs alternate, so the `AMOUNT_FOLDS` consecutive blocks used as folds
+/// are balanced as well. The pairs can be written (`write_pairs`) and read again using
+/// `pairs::PairsFile`, resulting in the same pairs and folds.
+pub struct Folders {
+    /// Pairs with paths relative to `basepath`
+    pairs: Vec<(IsSamePerson, String, String)>,
+
+    basepath: String,
+}
+
+/// Returns all identities in `basepath` with their images (relative to `basepath`), sorted by
+/// name so that the generated pairs don't depend on the order of the file system.
+fn scan(basepath: &str) -> Result<Vec<Vec<String>>, Error> {
+    let mut identities = Vec::new();
+    for dir in std::fs::read_dir(basepath)? {
+        let dir = dir?.path();
+        if !dir.is_dir() {
+            continue;
+        }
+
+        let identity = dir.file_name().unwrap_or_default().to_string_lossy();
+        let mut images = Vec::new();
+        for file in std::fs::read_dir(&dir)? {
+            let file = file?.path();
+            let is_image = file.extension().is_some_and(|ext| {
+                IMAGE_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str())
+            });
+            if is_image {
+                let name = file.file_name().unwrap_or_default().to_string_lossy();
+                images.push(format!("{identity}/{name}"));
+            }
+        }
+        images.sort();
+        identities.push((identity.to_string(), images));
+    }
+    identities.sort();
+    Ok(identities.into_iter().map(|(_, images)| images).collect())
+}
+
+impl Folders {
+    /// Scans `basepath` and generates pairs according to `generation`.
+    ///
+    /// # Errors
+    /// - `Error::IoError` returned, if `basepath` can't be scanned
+    /// - `Error::NotEnoughImages` returned, if no genuine or no impostor pair can be generated
+    pub fn new(basepath: String, generation: &PairGeneration) -> Result<Self, Error> {
+        let identities = scan(&basepath)?;
+        let mut rng = StdRng::seed_from_u64(generation.seed);
+
+        let mut genuine = Vec::new();
+        for images in &identities {
+            let mut candidates = Vec::new();
+            for (idx, image1) in images.iter().enumerate() {
+                for image2 in &images[idx + 1..] {
+                    candidates.push((image1, image2));
+                }
+            }
+            candidates.shuffle(&mut rng);
+            genuine.extend(candidates.into_iter().take(generation.max_per_identity));
+        }
+        genuine.shuffle(&mut rng);
+        genuine.truncate(generation.amount / 2);
+
+        let impostor = Self::impostors(&identities, genuine.len(), generation, &mut rng);
+        if impostor.is_empty() {
+            return Err(Error::NotEnoughImages);
+        }
+        // Less impostor pairs are found if `max_per_identity` is reached for most identities
+        genuine.truncate(impostor.len());
+
+        let genuine = genuine
+            .into_iter()
+            .map(|(image1, image2)| (true, image1.clone(), image2.clone()));
+        let impostor = impostor
+            .into_iter()
+            .map(|(image1, image2)| (false, image1.clone(), image2.clone()));
+        let pairs = genuine.interleave(impostor).collect();
+
+        Ok(Self { pairs, basepath })
+    }
+
+    /// Draws up to `amount` distinct pairs of images of different identities. Each identity is
+    /// part of at most `max_per_identity` pairs.
+    fn impostors<'a>(
+        identities: &'a [Vec<String>],
+        amount: usize,
+        generation: &PairGeneration,
+        rng: &mut StdRng,
+    ) -> Vec<(&'a String, &'a String)> {
+        let identities: Vec<&Vec<String>> = identities.iter().filter(|i| !i.is_empty()).collect();
+        if identities.len() < 2 {
+            return Vec::new();
+        }
+
+        let mut used: HashMap<usize, usize> = HashMap::new();
+        let mut seen = HashSet::new();
+        let mut ret = Vec::new();
+        for _ in 0..amount * IMPOSTOR_ATTEMPTS {
+            if ret.len() == amount {
+                break;
+            }
+
+            let id1 = rng.gen_range(0..identities.len());
+            let id2 = rng.gen_range(0..identities.len());
+            let exhausted = |id| used.get(&id).copied().unwrap_or(0) >= generation.max_per_identity;
+            if id1 == id2 || exhausted(id1) || exhausted(id2) {
+                continue;
+            }
+
+            let image1 = identities[id1].choose(rng).unwrap(); // unwrap okay; no empty identities
+            let image2 = identities[id2].choose(rng).unwrap();
+            if seen.insert((image1.min(image2), image1.max(image2))) {
+                *used.entry(id1).or_insert(0) += 1;
+                *used.entry(id2).or_insert(0) += 1;
+                ret.push((image1, image2));
+            }
+        }
+        ret
+    }
+
+    /// Writes the generated pairs as `path1<TAB>path2<TAB>label` lines (paths relative to the base
+    /// directory), readable by `pairs::PairsFile`.
+    ///
+    /// # Errors
+    /// - `Error::IoError` returned, if `path` can't be written
+    pub fn write_pairs(&self, path: &str) -> Result<(), Error> {
+        let lines: String = self
+            .pairs
+            .iter()
+            .map(|(same_person, path1, path2)| {
+                format!("{path1}\t{path2}\t{}\n", *same_person as u8)
+            })
+            .collect();
+        std::fs::write(path, lines)?;
+        Ok(())
+    }
+}
+
+impl Dataset for Folders {
+    fn pairs(&self) -> Vec<(bool, String, String)> {
+        self.pairs
+            .iter()
+            .map(|(same_person, path1, path2)| {
+                (
+                    *same_person,
+                    format!("{}/{path1}", self.basepath),
+                    format!("{}/{path2}", self.basepath),
+                )
+            })
+            .collect()
+    }
+
+    fn fold(&self, idx: usize) -> usize {
+        idx * AMOUNT_FOLDS / self.pairs.len()
+    }
+
+    fn name(&self) -> String {
+        let dir = Path::new(&self.basepath).file_name().unwrap_or_default();
+        format!("folders-{}", dir.to_string_lossy())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pairs::PairsFile;
+
+    /// Creates `<temp>/<name>` with 6 identities of 4 images each, an identity with a single image
+    /// and files that are no images.
+    fn tree(name: &str) -> String {
+        let basepath = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&basepath);
+        for id in 0..7 {
+            let dir = basepath.join(format!("person{id}"));
+            std::fs::create_dir_all(&dir).unwrap();
+            for image in 0..if id == 6 { 1 } else { 4 } {
+                std::fs::write(dir.join(format!("{image}.jpg")), "").unwrap();
+            }
+            std::fs::write(dir.join("notes.txt"), "").unwrap();
+        }
+        std::fs::write(basepath.join("README"), "").unwrap();
+        basepath.to_string_lossy().to_string()
+    }
+
+    fn identity(path: &str) -> &str {
+        Path::new(path)
+            .parent()
+            .unwrap()
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+    }
+
+    #[test]
+    fn generation() {
+        let basepath = tree("reducedemb-folders");
+        let generation = PairGeneration {
+            amount: 20,
+            max_per_identity: 2,
+            seed: 3,
+        };
+        let data = Folders::new(basepath.clone(), &generation).unwrap();
+        let pairs = data.pairs();
+        assert_eq!(
+            pairs,
+            Folders::new(basepath.clone(), &generation).unwrap().pairs()
+        );
+
+        // Balanced and alternating, at most `amount` pairs
+        assert!(!pairs.is_empty() && pairs.len() <= generation.amount);
+        assert_eq!(pairs.len() % 2, 0);
+        for (idx, (same_person, path1, path2)) in pairs.iter().enumerate() {
+            assert_eq!(*same_person, idx % 2 == 0);
+            assert_eq!(*same_person, identity(path1) == identity(path2));
+            assert!(path1.ends_with(".jpg") && path2.ends_with(".jpg"));
+        }
+
+        // `max_per_identity` genuine and impostor pairs per identity
+        for same_person in [true, false] {
+            let mut used: HashMap<&str, usize> = HashMap::new();
+            for (_, path1, path2) in pairs.iter().filter(|pair| pair.0 == same_person) {
+                let ids: HashSet<&str> = [identity(path1), identity(path2)].into();
+                ids.into_iter()
+                    .for_each(|id| *used.entry(id).or_insert(0) += 1);
+            }
+            assert!(used
+                .values()
+                .all(|amount| *amount <= generation.max_per_identity));
+        }
+
+        // Written pairs result in the same pairs and folds
+        let file = format!("{basepath}.txt");
+        data.write_pairs(&file).unwrap();
+        let read = PairsFile::new(&file, basepath.clone()).unwrap();
+        assert_eq!(read.pairs(), pairs);
+        assert!((0..pairs.len()).all(|idx| read.fold(idx) == data.fold(idx)));
+
+        std::fs::remove_file(file).unwrap();
+        std::fs::remove_dir_all(basepath).unwrap();
+    }
+
+    #[test]
+    fn not_enough_images() {
+        let basepath = tree("reducedemb-folders-single");
+        for id in 1..7 {
+            std::fs::remove_dir_all(Path::new(&basepath).join(format!("person{id}"))).unwrap();
+        }
+        let result = Folders::new(basepath.clone(), &PairGeneration::default());
+        assert!(matches!(result, Err(Error::NotEnoughImages)));
+        std::fs::remove_dir_all(basepath).unwrap();
+    }
+}
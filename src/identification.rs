@@ -0,0 +1,226 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+//! 1:N identification, i.e. searching a probe in a gallery of enrolled identities.
+
+use std::collections::BTreeMap;
+
+use crate::arcface::Recognition;
+use crate::distance::Distance;
+use crate::Dataset;
+
+/// Gallery/probe split of all images of a dataset with embedding.
+///
+/// Identities are sorted by name. Of each identity with multiple images, the first image (sorted
+/// by path) is enrolled in the gallery and all other images are mated probes. Images of
+/// identities with a single image are non-mated probes, i.e. their identity is not enrolled.
+pub struct Split {
+    /// Enrolled embeddings, one per identity
+    pub gallery: Vec<(usize, Vec<f32>)>,
+
+    /// Probes whose identity is enrolled
+    pub mated: Vec<(usize, Vec<f32>)>,
+
+    /// Probes whose identity is not enrolled
+    pub non_mated: Vec<Vec<f32>>,
+}
+
+/// Results of searching all probes of a `Split`.
+pub struct Identification {
+    /// Cumulative match characteristic: element `k` is the rate of mated probes whose identity is
+    /// among the `k + 1` closest gallery entries
+    pub cmc: Vec<f32>,
+
+    /// Detection and identification rate at each requested false positive identification rate,
+    /// i.e. the rate of mated probes whose identity is the closest gallery entry and closer than
+    /// a threshold only accepting the given rate of non-mated probes
+    pub dir: Vec<f32>,
+}
+
+impl Identification {
+    /// Rate of mated probes whose identity is among the `rank` closest gallery entries. `rank` is
+    /// clamped to 1..=`max_rank` of `Split::evaluate`.
+    pub fn rank(&self, rank: usize) -> f32 {
+        self.cmc[rank.clamp(1, self.cmc.len()) - 1]
+    }
+}
+
+impl Split {
+    /// Splits all images of `data` with embedding (see `Recognition::embedding`).
+    pub fn new(data: &dyn Dataset, rec: &mut Recognition) -> Self {
+        let mut identities: BTreeMap<String, Vec<Vec<f32>>> = BTreeMap::new();
+        for image in data.images() {
+            if let Some(emb) = rec.embedding(&image) {
+                identities
+                    .entry(data.identity(&image))
+                    .or_default()
+                    .push(emb);
+            }
+        }
+
+        let mut ret = Self {
+            gallery: Vec::new(),
+            mated: Vec::new(),
+            non_mated: Vec::new(),
+        };
+        for (id, embs) in identities.into_values().enumerate() {
+            let mut embs = embs.into_iter();
+            let first = embs.next().unwrap(); // unwrap okay; identities have at least one image
+            if embs.len() == 0 {
+                ret.non_mated.push(first);
+            } else {
+                ret.gallery.push((id, first));
+                ret.mated.extend(embs.map(|emb| (id, emb)));
+            }
+        }
+        ret
+    }
+
+    /// Returns the split with `reduce` applied to all embeddings.
+    pub fn map<F: Fn(&[f32]) -> Vec<f32>>(&self, reduce: F) -> Self {
+        Self {
+            gallery: self
+                .gallery
+                .iter()
+                .map(|(id, e)| (*id, reduce(e)))
+                .collect(),
+            mated: self.mated.iter().map(|(id, e)| (*id, reduce(e))).collect(),
+            non_mated: self.non_mated.iter().map(|e| reduce(e)).collect(),
+        }
+    }
+
+    /// Searches all probes in the gallery using `metric`. The CMC is calculated up to
+    /// `max_rank`, the detection and identification rate at each of `fpirs`. Without mated
+    /// probes, all rates are NaN.
+    ///
+    /// # Panics
+    /// - Panics if `max_rank` is 0 or a distance can't be compared (e.g. NaN)
+    pub fn evaluate(&self, metric: Distance, max_rank: usize, fpirs: &[f32]) -> Identification {
+        assert!(max_rank > 0, "max_rank has to be at least 1");
+        let mut ranks = vec![0; max_rank];
+        // Distance to the own gallery entry of all mated probes identified at rank 1
+        let mut identified = Vec::new();
+        for (id, probe) in &self.mated {
+            let dists: Vec<(usize, f32)> = self
+                .gallery
+                .iter()
+                .map(|(gallery_id, emb)| (*gallery_id, metric.calc(probe, emb)))
+                .collect();
+            let mate = dists
+                .iter()
+                .find(|(gallery_id, _)| gallery_id == id)
+                .unwrap()
+                .1;
+            let rank = dists.iter().filter(|(_, dist)| *dist < mate).count();
+            if rank < max_rank {
+                ranks[rank] += 1;
+            }
+            if rank == 0 {
+                identified.push(mate);
+            }
+        }
+
+        let amount = self.mated.len() as f32;
+        let cmc = ranks
+            .iter()
+            .scan(0, |sum, amount_rank| {
+                *sum += amount_rank;
+                Some(*sum)
+            })
+            .map(|sum| sum as f32 / amount)
+            .collect();
+
+        let mut non_mated: Vec<f32> = self
+            .non_mated
+            .iter()
+            .map(|probe| {
+                self.gallery
+                    .iter()
+                    .map(|(_, emb)| metric.calc(probe, emb))
+                    .fold(f32::INFINITY, f32::min)
+            })
+            .collect();
+        non_mated.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let dir = fpirs
+            .iter()
+            .map(|fpir| {
+                // Probes are accepted if their distance is below the one of the first non-mated
+                // probe exceeding `fpir`
+                let accepted = (fpir * non_mated.len() as f32) as usize;
+                let threshold = non_mated.get(accepted).copied().unwrap_or(f32::INFINITY);
+                identified.iter().filter(|dist| **dist < threshold).count() as f32 / amount
+            })
+            .collect();
+
+        Identification { cmc, dir }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rank_is_clamped() {
+        let identification = Identification {
+            cmc: vec![0.5, 0.75, 1.],
+            dir: vec![],
+        };
+        assert_eq!(identification.rank(0), 0.5);
+        assert_eq!(identification.rank(1), 0.5);
+        assert_eq!(identification.rank(3), 1.);
+        assert_eq!(identification.rank(10), 1.);
+    }
+
+    fn split() -> Split {
+        let embs = |values: &[(usize, f32)]| -> Vec<(usize, Vec<f32>)> {
+            values.iter().map(|(id, x)| (*id, vec![*x])).collect()
+        };
+        Split {
+            gallery: embs(&[(0, 0.), (1, 10.), (2, 20.)]),
+            mated: embs(&[(0, 1.), (1, 14.), (2, 12.), (0, 6.)]),
+            non_mated: vec![vec![3.], vec![15.], vec![30.]],
+        }
+    }
+
+    #[test]
+    fn evaluate() {
+        // Squared distances of the mated probes to the gallery:
+        // 1: 1, 81, 361 -> rank 1 | 14: 196, 16, 36 -> rank 1
+        // 12: 144, 4, 64 -> rank 2 | 6: 36, 16, 196 -> rank 2
+        // Closest gallery entries of the non-mated probes: 9, 25 and 100
+        let identification = split().evaluate(Distance::L2, 3, &[0., 0.5, 1.]);
+        assert_eq!(identification.cmc, [0.5, 1., 1.]);
+        assert_eq!(identification.rank(1), 0.5);
+        assert_eq!(identification.rank(5), 1.);
+
+        // Thresholds 9, 25 and infinity, applied to the rank 1 distances 1 and 16
+        assert_eq!(identification.dir, [0.25, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn evaluate_without_mated_probes() {
+        let split = Split {
+            mated: Vec::new(),
+            ..split()
+        };
+        let identification = split.evaluate(Distance::L2, 2, &[0.1]);
+        assert!(identification.cmc.iter().all(|rate| rate.is_nan()));
+        assert!(identification.dir[0].is_nan());
+    }
+}
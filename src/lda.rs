@@ -0,0 +1,238 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+//! Supervised projection trained on identity labels: within-class whitening followed by linear
+//! discriminant analysis (LDA).
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use crate::arcface::Recognition;
+use crate::pca::{eigen, fit_images};
+use crate::Dataset;
+
+/// Added to each eigenvalue of the within-class scatter, relative to their mean, so that the
+/// whitening stays stable for directions with (almost) no within-class variance
+const REGULARIZATION: f64 = 1e-3;
+
+/// Returns the embeddings of the images selected by `pca::fit_images`, grouped by identity.
+///
+/// Identities shown on any image of `exclude` (the evaluated dataset) are left out, so that the
+/// training and evaluation identities are disjoint. Identities with less than two embeddings
+/// are left out as well, as they don't contribute any within-class variance.
+pub fn fit_identities(
+    data: &dyn Dataset,
+    rec: &mut Recognition,
+    fold: Option<usize>,
+    exclude: &dyn Dataset,
+) -> Vec<Vec<Vec<f32>>> {
+    let excluded = exclude
+        .images()
+        .iter()
+        .map(|image| exclude.identity(image))
+        .collect();
+    group(data, rec, fit_images(data, fold), &excluded)
+}
+
+/// Same as `fit_identities`, but for cross-validation on the folds of `data`: returns the
+/// embeddings of the images of all folds except `fold`, leaving out the identities shown in
+/// `fold`.
+pub fn fit_folds(data: &dyn Dataset, rec: &mut Recognition, fold: usize) -> Vec<Vec<Vec<f32>>> {
+    let mut images = BTreeSet::new();
+    let mut excluded = HashSet::new();
+    for (idx, (_, path1, path2)) in data.pairs().into_iter().enumerate() {
+        if data.fold(idx) == fold {
+            excluded.insert(data.identity(&path1));
+            excluded.insert(data.identity(&path2));
+        } else {
+            images.insert(path1);
+            images.insert(path2);
+        }
+    }
+    group(data, rec, images, &excluded)
+}
+
+/// Groups the embeddings of `images` by identity, leaving out `excluded` identities and
+/// identities with less than two embeddings.
+fn group(
+    data: &dyn Dataset,
+    rec: &mut Recognition,
+    images: BTreeSet<String>,
+    excluded: &HashSet<String>,
+) -> Vec<Vec<Vec<f32>>> {
+    let mut identities: BTreeMap<String, Vec<Vec<f32>>> = BTreeMap::new();
+    for image in images {
+        let identity = data.identity(&image);
+        if excluded.contains(&identity) {
+            continue;
+        }
+        if let Some(emb) = rec.embedding(&image) {
+            identities.entry(identity).or_default().push(emb);
+        }
+    }
+    identities
+        .into_values()
+        .filter(|embs| embs.len() > 1)
+        .collect()
+}
+
+/// Discriminant components of a set of labelled embeddings.
+pub struct Lda {
+    /// Mean of the embeddings the components have been fitted on
+    pub mean: Vec<f32>,
+
+    /// Components, sorted by descending between-class variance. Projected embeddings have unit
+    /// within-class variance along each component. As the between-class scatter of `C`
+    /// identities has a rank of at most `C - 1`, only the first `C - 1` components are kept.
+    pub components: Vec<Vec<f32>>,
+
+    /// Between-class variance along each component, i.e. the ratio of between-class to
+    /// within-class variance
+    pub ratios: Vec<f32>,
+}
+
+impl Lda {
+    /// Whitens the within-class scatter of `identities` (embeddings grouped by identity) and
+    /// calculates the directions of largest between-class scatter in the whitened space.
+    ///
+    /// # Panics
+    /// - Panics if there are less than two identities or one of them is empty
+    pub fn fit(identities: &[Vec<Vec<f32>>]) -> Self {
+        assert!(identities.len() > 1, "LDA needs at least two identities");
+        let dims = identities[0][0].len();
+        let amount = identities.iter().map(Vec::len).sum::<usize>() as f64;
+
+        let means: Vec<Vec<f64>> = identities.iter().map(|embs| mean(embs)).collect();
+        let mut total = vec![0_f64; dims];
+        for (embs, m) in identities.iter().zip(&means) {
+            for (t, x) in total.iter_mut().zip(m) {
+                *t += x * embs.len() as f64 / amount;
+            }
+        }
+
+        let mut within = vec![vec![0_f64; dims]; dims];
+        let mut between = vec![vec![0_f64; dims]; dims];
+        for (embs, m) in identities.iter().zip(&means) {
+            for emb in embs {
+                let centered: Vec<f64> = emb.iter().zip(m).map(|(x, m)| *x as f64 - m).collect();
+                add_outer(&mut within, &centered, 1. / amount);
+            }
+            let centered: Vec<f64> = m.iter().zip(&total).map(|(x, t)| x - t).collect();
+            add_outer(&mut between, &centered, embs.len() as f64 / amount);
+        }
+
+        // Whitening matrix: eigenvectors of the within-class scatter, scaled by the inverse
+        // square root of their eigenvalues
+        let (eigenvalues, mut whitening) = eigen(within);
+        let epsilon = REGULARIZATION * eigenvalues.iter().sum::<f64>() / dims as f64;
+        for row in whitening.iter_mut() {
+            for (w, value) in row.iter_mut().zip(&eigenvalues) {
+                *w /= (value.max(0.) + epsilon).sqrt();
+            }
+        }
+
+        // Between-class scatter in the whitened space
+        let whitened = multiply(&transpose(&whitening), &multiply(&between, &whitening));
+        let (eigenvalues, eigenvectors) = eigen(whitened);
+        let components = transpose(&multiply(&whitening, &eigenvectors));
+
+        let mut order: Vec<usize> = (0..dims).collect();
+        order.sort_by(|a, b| eigenvalues[*b].total_cmp(&eigenvalues[*a]));
+        order.truncate(identities.len() - 1);
+
+        Self {
+            mean: total.iter().map(|t| *t as f32).collect(),
+            components: order
+                .iter()
+                .map(|&c| components[c].iter().map(|x| *x as f32).collect())
+                .collect(),
+            ratios: order.iter().map(|&c| eigenvalues[c] as f32).collect(),
+        }
+    }
+
+    /// Projects `emb` onto the first `amount` components.
+    pub fn project(&self, emb: &[f32], amount: usize) -> Vec<f32> {
+        let centered: Vec<f32> = emb.iter().zip(&self.mean).map(|(x, m)| x - m).collect();
+        self.components[..amount]
+            .iter()
+            .map(|c| c.iter().zip(&centered).map(|(a, b)| a * b).sum())
+            .collect()
+    }
+}
+
+fn mean(embs: &[Vec<f32>]) -> Vec<f64> {
+    let mut ret = vec![0_f64; embs[0].len()];
+    for emb in embs {
+        for (m, x) in ret.iter_mut().zip(emb) {
+            *m += *x as f64 / embs.len() as f64;
+        }
+    }
+    ret
+}
+
+/// Adds `weight * x * x^T` to `matrix`.
+fn add_outer(matrix: &mut [Vec<f64>], x: &[f64], weight: f64) {
+    for (row, a) in matrix.iter_mut().zip(x) {
+        for (m, b) in row.iter_mut().zip(x) {
+            *m += weight * a * b;
+        }
+    }
+}
+
+fn transpose(matrix: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    (0..matrix[0].len())
+        .map(|j| matrix.iter().map(|row| row[j]).collect())
+        .collect()
+}
+
+fn multiply(a: &[Vec<f64>], b: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    a.iter()
+        .map(|row| {
+            let mut ret = vec![0_f64; b[0].len()];
+            for (x, row_b) in row.iter().zip(b) {
+                for (r, y) in ret.iter_mut().zip(row_b) {
+                    *r += x * y;
+                }
+            }
+            ret
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn components_per_identity() {
+        // Identities differ along the first dimension only
+        let identities: Vec<Vec<Vec<f32>>> = (0..3)
+            .map(|id| {
+                (0..4)
+                    .map(|i| vec![id as f32, (i % 2) as f32, (i / 2) as f32])
+                    .collect()
+            })
+            .collect();
+        let lda = Lda::fit(&identities);
+        assert_eq!(lda.components.len(), 2);
+
+        let a = lda.project(&identities[0][0], 1);
+        let b = lda.project(&identities[0][3], 1);
+        let c = lda.project(&identities[1][0], 1);
+        assert!((a[0] - b[0]).abs() < 1e-3);
+        assert!((a[0] - c[0]).abs() > 1.);
+    }
+}
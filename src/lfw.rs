@@ -18,7 +18,7 @@
 use csv::StringRecord;
 use serde::Deserialize;
 
-use crate::Dataset;
+use crate::{Dataset, AMOUNT_FOLDS};
 
 #[derive(Debug)]
 /// All possible Lfw Errors
@@ -110,29 +110,34 @@ impl Lfw {
 }
 
 impl Dataset for Lfw {
-    fn embeddings(&self, rec: &mut crate::arcface::Recognition) -> Vec<(bool, Vec<f32>, Vec<f32>)> {
+    fn pairs(&self) -> Vec<(bool, String, String)> {
         self.pairs
             .iter()
-            .map(|p| {
-                (
-                    p.same_person(),
-                    rec.get(p.get_path1().into()).unwrap().clone(),
-                    rec.get(p.get_path2().into()).unwrap().clone(),
-                )
-            })
+            .map(|p| (p.same_person(), p.get_path1(), p.get_path2()))
             .collect()
     }
 
-    fn images(&self) -> Vec<String> {
-        let mut ret = Vec::new();
-        for pair in &self.pairs {
-            ret.push(pair.get_path1());
-            ret.push(pair.get_path2());
-        }
-        ret
+    /// The pairs file consists of `AMOUNT_FOLDS` consecutive blocks with the same amount of pairs.
+    fn fold(&self, idx: usize) -> usize {
+        idx * AMOUNT_FOLDS / self.pairs.len()
     }
 
     fn name(&self) -> String {
         "lfw".into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_of_equal_size() {
+        let data = Lfw::new("data/lfw-pairs.txt", String::new()).unwrap();
+        let mut sizes = [0; AMOUNT_FOLDS];
+        (0..data.pairs.len()).for_each(|idx| sizes[data.fold(idx)] += 1);
+        assert!(sizes
+            .iter()
+            .all(|size| *size == data.pairs.len() / AMOUNT_FOLDS));
+    }
+}
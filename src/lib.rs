@@ -0,0 +1,176 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+//! Analysis of reduced (truncated, subsampled and quantized) facial embeddings.
+//!
+//! Datasets (`lfw::Lfw`, `cplfw::Cplfw` (also CALFW), `agedb::AgeDb`, `cfp::Cfp`,
+//! `pairs::PairsFile`, `folders::Folders`, `templates::Templates`) provide pairs of images,
+//! `arcface::Recognition` computes and caches their embeddings, `profile::ReductionProfile`
+//! reduces them and `eval` evaluates the reduced embeddings. `actions` contains the experiments
+//! exposed by the command line interface.
+
+pub mod actions;
+pub mod agedb;
+pub mod arcface;
+pub mod cache;
+pub mod cfp;
+pub mod cplfw;
+pub mod detection;
+pub mod distance;
+pub mod eval;
+pub mod failure;
+pub mod folders;
+pub mod identification;
+pub mod lda;
+pub mod lfw;
+pub mod misc;
+pub mod pairs;
+pub mod pca;
+pub mod pq;
+pub mod profile;
+pub mod projection;
+pub mod roc;
+pub mod templates;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use kdam::tqdm;
+
+pub type IsSamePerson = bool;
+
+/// Amount of folds used by the 10-fold evaluation protocol.
+pub const AMOUNT_FOLDS: usize = 10;
+
+/// All pairs of a single fold.
+pub type Fold = Vec<(IsSamePerson, Vec<f32>, Vec<f32>)>;
+
+/// Pairs of face images with their folds, implemented by every supported dataset. Embeddings are
+/// calculated and cached by `Recognition`.
+pub trait Dataset {
+    /// All pairs of the dataset as `(same_person, path1, path2)`, in the order of the pairs file.
+    fn pairs(&self) -> Vec<(IsSamePerson, String, String)>;
+
+    /// Index of the fold (< `AMOUNT_FOLDS`) the pair with index `idx` of `pairs` belongs to.
+    fn fold(&self, idx: usize) -> usize;
+
+    fn name(&self) -> String;
+
+    /// Embeddings of all pairs. Pairs with missing embeddings are handled according to
+    /// `rec.missing`, i.e. they are skipped or have empty embeddings (see `Recognition::pair`).
+    fn embeddings(
+        &self,
+        rec: &mut crate::arcface::Recognition,
+    ) -> Vec<(IsSamePerson, Vec<f32>, Vec<f32>)> {
+        self.pairs()
+            .into_iter()
+            .filter_map(|(same_person, path1, path2)| rec.pair(same_person, &path1, &path2))
+            .collect()
+    }
+
+    /// Same as `embeddings`, but split into the `AMOUNT_FOLDS` official folds of the dataset.
+    fn folds(&self, rec: &mut crate::arcface::Recognition) -> Vec<Fold> {
+        let mut folds = vec![Vec::new(); AMOUNT_FOLDS];
+        for (idx, (same_person, path1, path2)) in self.pairs().into_iter().enumerate() {
+            if let Some(pair) = rec.pair(same_person, &path1, &path2) {
+                folds[self.fold(idx)].push(pair);
+            }
+        }
+        folds
+    }
+
+    /// All images used by at least one pair, without duplicates and sorted by path.
+    fn images(&self) -> BTreeSet<String> {
+        let mut ret = BTreeSet::new();
+        for (_, path1, path2) in self.pairs() {
+            ret.insert(path1);
+            ret.insert(path2);
+        }
+        ret
+    }
+
+    /// Identity shown on image `path`. By default the name of the directory containing the image.
+    fn identity(&self, path: &str) -> String {
+        Path::new(path)
+            .parent()
+            .and_then(Path::file_name)
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Counts the unique images and identities of all pairs.
+    fn stats(&self) -> Stats {
+        let pairs = self.pairs();
+        let mut pairs_per_identity = BTreeMap::new();
+        for (_, path1, path2) in &pairs {
+            let identities = BTreeSet::from([self.identity(path1), self.identity(path2)]);
+            for identity in identities {
+                *pairs_per_identity.entry(identity).or_insert(0) += 1;
+            }
+        }
+
+        Stats {
+            images: self.images().len(),
+            same_pairs: pairs
+                .iter()
+                .filter(|(same_person, _, _)| *same_person)
+                .count(),
+            diff_pairs: pairs
+                .iter()
+                .filter(|(same_person, _, _)| !same_person)
+                .count(),
+            pairs_per_identity,
+        }
+    }
+
+    /// Templates of the dataset, if it consists of templates (see `templates::Templates`).
+    fn templates(&self) -> Option<&templates::Templates> {
+        None
+    }
+
+    fn cache(&self, rec: &mut crate::arcface::Recognition) {
+        for filename in tqdm!(self.images().iter()) {
+            rec.cache_img(&filename.into());
+        }
+    }
+
+    /// Same as `cache`, but using `workers` threads (see `Recognition::cache_parallel`).
+    fn cache_parallel(&self, rec: &mut crate::arcface::Recognition, workers: usize) {
+        let images: Vec<PathBuf> = self.images().iter().map(PathBuf::from).collect();
+        rec.cache_parallel(&images, workers);
+    }
+}
+
+/// Size of a dataset, see `Dataset::stats`.
+pub struct Stats {
+    /// Amount of unique images
+    pub images: usize,
+
+    pub same_pairs: usize,
+    pub diff_pairs: usize,
+
+    /// Amount of pairs each identity occurs in, i.e. its length is the amount of unique
+    /// identities
+    pub pairs_per_identity: BTreeMap<String, usize>,
+}
+
+/// Returns false for pairs rejected because of a missing embedding (see `Recognition::pair`).
+pub fn is_complete((_, emb1, emb2): &(IsSamePerson, Vec<f32>, Vec<f32>)) -> bool {
+    !emb1.is_empty() && !emb2.is_empty()
+}
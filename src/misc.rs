@@ -15,18 +15,18 @@
 // See the Licence for the specific language governing permissions and
 // limitations under the Licence.
 
-pub(crate) struct ConfusionMatrix {
+pub struct ConfusionMatrix {
     /// true-positives
-    pub(crate) tp: i32,
+    pub tp: i32,
 
     /// false-negatives
-    pub(crate) fne: i32,
+    pub fne: i32,
 
     /// true-negatives
-    pub(crate) tn: i32,
+    pub tn: i32,
 
     /// false-positives
-    pub(crate) fp: i32,
+    pub fp: i32,
 }
 
 impl ConfusionMatrix {
@@ -42,6 +42,59 @@ impl ConfusionMatrix {
         Self { tp, fne, tn, fp }
     }
 
+    /// Calculates the confusion matrix for every possible threshold, i.e. every distinct value of
+    /// `same` and `diff`, in a single pass over the sorted distances. The returned thresholds are
+    /// sorted ascending.
+    ///
+    /// # Panics
+    /// - Panics if a distance can't be compared (e.g. NaN)
+    pub fn sweep<T: std::cmp::PartialOrd + Copy>(same: &[T], diff: &[T]) -> Vec<(T, Self)> {
+        let mut dists: Vec<(T, bool)> = same
+            .iter()
+            .map(|d| (*d, true))
+            .chain(diff.iter().map(|d| (*d, false)))
+            .collect();
+        dists.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut ret = Vec::new();
+        let (mut tp, mut fp) = (0, 0);
+        for (idx, (dist, same_person)) in dists.iter().enumerate() {
+            match same_person {
+                true => tp += 1,
+                false => fp += 1,
+            }
+            // Only the last occurrence of equal distances is a valid threshold
+            if idx + 1 == dists.len() || dists[idx + 1].0 != *dist {
+                let conf = Self {
+                    tp,
+                    fne: same.len() as i32 - tp,
+                    tn: diff.len() as i32 - fp,
+                    fp,
+                };
+                ret.push((*dist, conf));
+            }
+        }
+        ret
+    }
+
+    /// Returns the threshold minimizing `amount_false`, together with its confusion matrix. If
+    /// several thresholds are optimal, the smallest one is returned.
+    ///
+    /// # Panics
+    /// - Panics if both `same` and `diff` are empty
+    pub fn best<T: std::cmp::PartialOrd + Copy>(same: &[T], diff: &[T]) -> (T, Self) {
+        Self::sweep(same, diff)
+            .into_iter()
+            .min_by_key(|(_, conf)| conf.amount_false())
+            .unwrap()
+    }
+
+    /// Adds pairs rejected without calculating a distance, e.g. because of a missing face.
+    pub fn reject(&mut self, same: usize, diff: usize) {
+        self.fne += same as i32;
+        self.tn += diff as i32;
+    }
+
     pub fn amount_false(&self) -> i32 {
         self.fne + self.fp
     }
@@ -58,3 +111,63 @@ impl ConfusionMatrix {
         self.fne as f32 / (self.fne + self.tn) as f32
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(conf: &ConfusionMatrix) -> (i32, i32, i32, i32) {
+        (conf.tp, conf.fne, conf.tn, conf.fp)
+    }
+
+    /// Confusion matrices of every distinct distance, calculated independently of each other.
+    fn brute_force(same: &[f32], diff: &[f32]) -> Vec<(f32, (i32, i32, i32, i32))> {
+        let mut thresholds: Vec<f32> = same.iter().chain(diff).copied().collect();
+        thresholds.sort_by(|a, b| a.total_cmp(b));
+        thresholds.dedup();
+        thresholds
+            .into_iter()
+            .map(|t| {
+                (
+                    t,
+                    counts(&ConfusionMatrix::new(t, &same.to_vec(), &diff.to_vec())),
+                )
+            })
+            .collect()
+    }
+
+    fn check(same: &[f32], diff: &[f32]) {
+        let sweep: Vec<_> = ConfusionMatrix::sweep(same, diff)
+            .iter()
+            .map(|(t, conf)| (*t, counts(conf)))
+            .collect();
+        assert_eq!(sweep, brute_force(same, diff));
+    }
+
+    #[test]
+    fn sweep_matches_brute_force() {
+        check(&[0.1, 0.4, 0.2], &[0.9, 0.3, 0.7]);
+        check(&[0.5], &[]);
+        check(&[], &[0.5, 0.1]);
+    }
+
+    #[test]
+    fn sweep_with_ties() {
+        // Ties within same, within diff and across both
+        let same = [0.2, 0.2, 0.5, 0.7, 0.7];
+        let diff = [0.5, 0.5, 0.7, 0.9, 0.9];
+        check(&same, &diff);
+
+        let sweep = ConfusionMatrix::sweep(&same, &diff);
+        assert_eq!(sweep.len(), 4);
+        assert_eq!(sweep[1].0, 0.5);
+        assert_eq!(counts(&sweep[1].1), (3, 2, 3, 2));
+    }
+
+    #[test]
+    fn best_prefers_smallest_threshold() {
+        let (threshold, conf) = ConfusionMatrix::best(&[0.1, 0.3], &[0.2, 0.4]);
+        assert_eq!(threshold, 0.1);
+        assert_eq!(conf.amount_false(), 1);
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+use std::path::Path;
+
+use crate::{Dataset, IsSamePerson, AMOUNT_FOLDS};
+
+#[derive(Debug)]
+/// All possible PairsFile Errors
+pub enum Error {
+    /// Pairs file could not be read
+    CsvError(csv::Error),
+
+    /// The line with the given number does not consist of `path1<TAB>path2<TAB>label`
+    InvalidLine(usize),
+
+    /// A label is neither `1`/`true` (same person) nor `0`/`false` (different people)
+    InvalidLabel(String),
+}
+
+impl From<csv::Error> for Error {
+    fn from(value: csv::Error) -> Self {
+        Self::CsvError(value)
+    }
+}
+
+/// Generic dataset reading a pairs file consisting of `path1<TAB>path2<TAB>label` lines. Paths
+/// are relative to a base directory, the label is `1` (or `true`) for the same person and `0` (or
+/// `false`) for different people. Lines starting with `#` are ignored.
+///
+/// As there are no official folds, the pairs are split into `AMOUNT_FOLDS` consecutive blocks.
+pub struct PairsFile {
+    pairs: Vec<(IsSamePerson, String, String)>,
+
+    /// Used to keep the caches of different pairs files apart
+    name: String,
+}
+
+impl PairsFile {
+    pub fn new(pairs_file: &str, basepath: String) -> Result<Self, Error> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .comment(Some(b'#'))
+            .delimiter(b'\t')
+            .from_path(pairs_file)?;
+
+        let mut pairs = Vec::new();
+        for record in reader.records() {
+            let record = record?;
+            if record.len() != 3 {
+                let line = record.position().map_or(0, |position| position.line());
+                return Err(Error::InvalidLine(line as usize));
+            }
+
+            let same_person = match &record[2] {
+                "1" | "true" => true,
+                "0" | "false" => false,
+                label => return Err(Error::InvalidLabel(label.into())),
+            };
+            pairs.push((
+                same_person,
+                format!("{basepath}/{}", &record[0]),
+                format!("{basepath}/{}", &record[1]),
+            ));
+        }
+
+        let name = Path::new(pairs_file)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Ok(Self { pairs, name })
+    }
+}
+
+impl Dataset for PairsFile {
+    fn pairs(&self) -> Vec<(bool, String, String)> {
+        self.pairs.clone()
+    }
+
+    fn fold(&self, idx: usize) -> usize {
+        idx * AMOUNT_FOLDS / self.pairs.len()
+    }
+
+    fn name(&self) -> String {
+        format!("pairs-{}", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(name: &str, content: &str) -> Result<PairsFile, Error> {
+        let file =
+            std::env::temp_dir().join(format!("reducedemb-{name}-{}.txt", std::process::id()));
+        std::fs::write(&file, content).unwrap();
+        let result = PairsFile::new(file.to_str().unwrap(), "/data".into());
+        std::fs::remove_file(file).unwrap();
+        result
+    }
+
+    #[test]
+    fn labels_and_comments() {
+        let content = "# path1\tpath2\tlabel\na/1.jpg\ta/2.jpg\t1\na/1.jpg\tb/1.jpg\t0\n\
+                       b/1.jpg\tb/2.jpg\ttrue\n# comment\nb/2.jpg\tc/1.jpg\tfalse\n";
+        let data = read("pairs-valid", content).unwrap();
+        assert_eq!(
+            data.pairs(),
+            [
+                (true, "/data/a/1.jpg", "/data/a/2.jpg"),
+                (false, "/data/a/1.jpg", "/data/b/1.jpg"),
+                (true, "/data/b/1.jpg", "/data/b/2.jpg"),
+                (false, "/data/b/2.jpg", "/data/c/1.jpg"),
+            ]
+            .map(|(same, path1, path2)| (same, path1.to_string(), path2.to_string()))
+        );
+        assert_eq!(
+            data.name(),
+            format!("pairs-reducedemb-pairs-valid-{}", std::process::id())
+        );
+    }
+
+    #[test]
+    fn malformed_lines() {
+        let content = "# comment\na/1.jpg\ta/2.jpg\t1\na/1.jpg\tb/1.jpg\n";
+        assert!(matches!(
+            read("pairs-short", content),
+            Err(Error::InvalidLine(3))
+        ));
+
+        let content = "a/1.jpg\ta/2.jpg\t1\textra\n";
+        assert!(matches!(
+            read("pairs-long", content),
+            Err(Error::InvalidLine(1))
+        ));
+
+        let content = "a/1.jpg a/2.jpg 1\n";
+        assert!(matches!(
+            read("pairs-spaces", content),
+            Err(Error::InvalidLine(1))
+        ));
+    }
+
+    #[test]
+    fn invalid_labels() {
+        for label in ["yes", "2", "True", ""] {
+            let content = format!("a/1.jpg\ta/2.jpg\t{label}\n");
+            match read("pairs-label", &content) {
+                Err(Error::InvalidLabel(invalid)) => assert_eq!(invalid, label),
+                _ => panic!("label {label:?} accepted"),
+            }
+        }
+    }
+}
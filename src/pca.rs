@@ -0,0 +1,217 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+//! Principal component analysis, as linear projection alternative to selecting dimensions.
+
+use std::collections::BTreeSet;
+
+use crate::arcface::Recognition;
+use crate::Dataset;
+
+/// Maximum amount of sweeps of the Jacobi eigenvalue algorithm
+const MAX_SWEEPS: usize = 50;
+
+/// Returns all images of `data`, or only the images of the pairs of `fold` if set.
+pub fn fit_images(data: &dyn Dataset, fold: Option<usize>) -> BTreeSet<String> {
+    match fold {
+        None => data.images(),
+        Some(fold) => data
+            .pairs()
+            .into_iter()
+            .enumerate()
+            .filter(|(idx, _)| data.fold(*idx) == fold)
+            .flat_map(|(_, (_, path1, path2))| [path1, path2])
+            .collect(),
+    }
+}
+
+/// Returns the embeddings of the images selected by `fit_images`. Images without embedding are
+/// left out.
+pub fn fit_embeddings(
+    data: &dyn Dataset,
+    rec: &mut Recognition,
+    fold: Option<usize>,
+) -> Vec<Vec<f32>> {
+    fit_images(data, fold)
+        .iter()
+        .filter_map(|image| rec.embedding(image))
+        .collect()
+}
+
+/// Principal components of a set of embeddings.
+pub struct Pca {
+    /// Mean of the embeddings the components have been fitted on
+    pub mean: Vec<f32>,
+
+    /// Unit length components, sorted by descending variance
+    pub components: Vec<Vec<f32>>,
+
+    /// Variance of the embeddings along each component
+    pub variances: Vec<f32>,
+}
+
+impl Pca {
+    /// Calculates the principal components of `embeddings`, i.e. the eigenvectors of their
+    /// covariance matrix.
+    ///
+    /// # Panics
+    /// - Panics if `embeddings` is empty
+    pub fn fit(embeddings: &[Vec<f32>]) -> Self {
+        let dims = embeddings[0].len();
+        let amount = embeddings.len() as f64;
+
+        let mut mean = vec![0_f64; dims];
+        for emb in embeddings {
+            for (m, x) in mean.iter_mut().zip(emb) {
+                *m += *x as f64 / amount;
+            }
+        }
+
+        let mut covariance = vec![vec![0_f64; dims]; dims];
+        for emb in embeddings {
+            let centered: Vec<f64> = emb.iter().zip(&mean).map(|(x, m)| *x as f64 - m).collect();
+            for (row, x) in covariance.iter_mut().zip(&centered) {
+                for (c, y) in row.iter_mut().zip(&centered) {
+                    *c += x * y / amount;
+                }
+            }
+        }
+
+        let (eigenvalues, eigenvectors) = eigen(covariance);
+        let mut order: Vec<usize> = (0..dims).collect();
+        order.sort_by(|a, b| eigenvalues[*b].total_cmp(&eigenvalues[*a]));
+
+        Self {
+            mean: mean.iter().map(|m| *m as f32).collect(),
+            components: order
+                .iter()
+                .map(|&c| eigenvectors.iter().map(|row| row[c] as f32).collect())
+                .collect(),
+            variances: order.iter().map(|&c| eigenvalues[c] as f32).collect(),
+        }
+    }
+
+    /// Projects `emb` onto the first `amount` components.
+    pub fn project(&self, emb: &[f32], amount: usize) -> Vec<f32> {
+        let centered: Vec<f32> = emb.iter().zip(&self.mean).map(|(x, m)| x - m).collect();
+        self.components[..amount]
+            .iter()
+            .map(|c| c.iter().zip(&centered).map(|(a, b)| a * b).sum())
+            .collect()
+    }
+}
+
+/// Eigenvalues and eigenvectors (as columns) of the symmetric `matrix`, calculated using the
+/// cyclic Jacobi eigenvalue algorithm.
+pub fn eigen(mut matrix: Vec<Vec<f64>>) -> (Vec<f64>, Vec<Vec<f64>>) {
+    let n = matrix.len();
+    let mut vectors = vec![vec![0_f64; n]; n];
+    for (i, row) in vectors.iter_mut().enumerate() {
+        row[i] = 1.;
+    }
+
+    let total: f64 = matrix.iter().flatten().map(|a| a * a).sum();
+    for _ in 0..MAX_SWEEPS {
+        let off_diagonal: f64 = (0..n)
+            .flat_map(|i| (0..n).filter(move |j| *j != i).map(move |j| (i, j)))
+            .map(|(i, j)| matrix[i][j] * matrix[i][j])
+            .sum();
+        if off_diagonal <= total * 1e-24 {
+            break;
+        }
+
+        for p in 0..n {
+            for q in p + 1..n {
+                if matrix[p][q] == 0. {
+                    continue;
+                }
+
+                // Rotation zeroing matrix[p][q]
+                let theta = (matrix[q][q] - matrix[p][p]) / (2. * matrix[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.).sqrt());
+                let c = 1. / (t * t + 1.).sqrt();
+                let s = t * c;
+
+                for row in matrix.iter_mut() {
+                    let (kp, kq) = (row[p], row[q]);
+                    row[p] = c * kp - s * kq;
+                    row[q] = s * kp + c * kq;
+                }
+                let (upper, lower) = matrix.split_at_mut(q);
+                for (pk, qk) in upper[p].iter_mut().zip(lower[0].iter_mut()) {
+                    (*pk, *qk) = (c * *pk - s * *qk, s * *pk + c * *qk);
+                }
+                for row in vectors.iter_mut() {
+                    let (kp, kq) = (row[p], row[q]);
+                    row[p] = c * kp - s * kq;
+                    row[q] = s * kp + c * kq;
+                }
+            }
+        }
+    }
+
+    ((0..n).map(|i| matrix[i][i]).collect(), vectors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eigen_decomposition() {
+        let matrix = vec![vec![4., 1., 0.5], vec![1., 3., -1.], vec![0.5, -1., 2.]];
+        let (values, vectors) = eigen(matrix.clone());
+
+        // trace is preserved
+        assert!((values.iter().sum::<f64>() - 9.).abs() < 1e-9);
+        for (c, value) in values.iter().enumerate() {
+            let v: Vec<f64> = vectors.iter().map(|row| row[c]).collect();
+            // A v = lambda v
+            for (row, x) in matrix.iter().zip(&v) {
+                let av: f64 = row.iter().zip(&v).map(|(a, b)| a * b).sum();
+                assert!((av - value * x).abs() < 1e-9);
+            }
+            // orthonormal columns
+            for d in 0..3 {
+                let dot: f64 = vectors.iter().map(|row| row[c] * row[d]).sum();
+                assert!((dot - if c == d { 1. } else { 0. }).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn eigen_of_known_matrix() {
+        let (mut values, _) = eigen(vec![vec![2., 1.], vec![1., 2.]]);
+        values.sort_by(|a, b| a.total_cmp(b));
+        assert!((values[0] - 1.).abs() < 1e-12);
+        assert!((values[1] - 3.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn fit_finds_main_direction() {
+        let embeddings: Vec<Vec<f32>> = (0..10)
+            .map(|i| vec![i as f32, i as f32 + if i % 2 == 0 { 0.1 } else { -0.1 }])
+            .collect();
+        let pca = Pca::fit(&embeddings);
+        let first = &pca.components[0];
+        // (1, 1) / sqrt(2), up to the sign
+        assert!((first[0].abs() - 0.5_f32.sqrt()).abs() < 1e-2);
+        assert!((first[0] - first[1]).abs() < 1e-2);
+        assert!(pca.variances[0] > pca.variances[1]);
+        assert_eq!(pca.project(&pca.mean.clone(), 2), [0., 0.]);
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+//! Product quantization: embeddings are split into sub-vectors, each stored as the index of its
+//! closest centroid, i.e. a single byte.
+
+use std::ops::Range;
+
+use rand::{rngs::StdRng, seq::index::sample, Rng, SeedableRng};
+
+use crate::distance::Distance;
+
+/// Maximum amount of centroids per sub-vector, so that each code fits into a byte
+const MAX_CENTROIDS: usize = 256;
+
+/// Amount of Lloyd iterations used to train each codebook
+const KMEANS_ITERATIONS: usize = 25;
+
+/// Codebooks of a product quantizer.
+pub struct ProductQuantizer {
+    /// Dimensions of each sub-vector
+    pub subspaces: Vec<Range<usize>>,
+
+    /// Centroids of each sub-vector
+    pub codebooks: Vec<Vec<Vec<f32>>>,
+}
+
+impl ProductQuantizer {
+    /// Splits the dimensions into `amount_subspaces` consecutive sub-vectors of (almost) equal
+    /// size and trains a codebook of up to 256 centroids for each of them using k-means on
+    /// `embeddings`. The same seed always results in the same codebooks.
+    ///
+    /// # Panics
+    /// - Panics if `embeddings` is empty or `amount_subspaces` exceeds the embedding size
+    pub fn train(embeddings: &[Vec<f32>], amount_subspaces: usize, seed: u64) -> Self {
+        let dims = embeddings[0].len();
+        assert!(amount_subspaces > 0 && amount_subspaces <= dims);
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let subspaces: Vec<Range<usize>> = (0..amount_subspaces)
+            .map(|s| s * dims / amount_subspaces..(s + 1) * dims / amount_subspaces)
+            .collect();
+        let codebooks = subspaces
+            .iter()
+            .map(|range| {
+                let data: Vec<&[f32]> = embeddings.iter().map(|e| &e[range.clone()]).collect();
+                kmeans(&data, MAX_CENTROIDS.min(data.len()), &mut rng)
+            })
+            .collect();
+        Self {
+            subspaces,
+            codebooks,
+        }
+    }
+
+    /// Amount of bytes needed to store an encoded embedding.
+    pub fn bytes(&self) -> usize {
+        self.subspaces.len()
+    }
+
+    /// Index of the closest centroid of each sub-vector of `emb`.
+    pub fn encode(&self, emb: &[f32]) -> Vec<u8> {
+        self.subspaces
+            .iter()
+            .zip(&self.codebooks)
+            .map(|(range, codebook)| closest(codebook, &emb[range.clone()]) as u8)
+            .collect()
+    }
+
+    /// Concatenates the centroids referenced by `codes`.
+    pub fn decode(&self, codes: &[u8]) -> Vec<f32> {
+        codes
+            .iter()
+            .zip(&self.codebooks)
+            .flat_map(|(code, codebook)| codebook[*code as usize].iter().copied())
+            .collect()
+    }
+
+    /// Asymmetric distance computation: distance between the unencoded `query` and the encoded
+    /// `codes`. For `Distance::L2`, this is the sum of the distances of each sub-vector of `query`
+    /// to its referenced centroid.
+    pub fn adc(&self, metric: Distance, query: &[f32], codes: &[u8]) -> f32 {
+        metric.calc(query, &self.decode(codes))
+    }
+
+    /// Symmetric distance computation: distance between two encoded embeddings. For
+    /// `Distance::L2`, this is the sum of the distances between the referenced centroids.
+    pub fn sdc(&self, metric: Distance, codes1: &[u8], codes2: &[u8]) -> f32 {
+        metric.calc(&self.decode(codes1), &self.decode(codes2))
+    }
+}
+
+/// Index of the centroid closest (squared euclidean distance) to `x`.
+fn closest(centroids: &[Vec<f32>], x: &[f32]) -> usize {
+    centroids
+        .iter()
+        .map(|c| Distance::L2.calc(c, x))
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map_or(0, |(idx, _)| idx)
+}
+
+/// Clusters `data` into `k` centroids using Lloyd's algorithm, initialized with `k` distinct
+/// random samples. Centroids losing all their samples are reinitialized with a random sample.
+fn kmeans(data: &[&[f32]], k: usize, rng: &mut StdRng) -> Vec<Vec<f32>> {
+    let mut centroids: Vec<Vec<f32>> = sample(rng, data.len(), k)
+        .into_iter()
+        .map(|idx| data[idx].to_vec())
+        .collect();
+
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut sums = vec![vec![0_f32; data[0].len()]; k];
+        let mut counts = vec![0_usize; k];
+        for x in data {
+            let idx = closest(&centroids, x);
+            counts[idx] += 1;
+            for (s, v) in sums[idx].iter_mut().zip(x.iter()) {
+                *s += v;
+            }
+        }
+
+        for ((centroid, sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+            *centroid = match count {
+                0 => data[rng.gen_range(0..data.len())].to_vec(),
+                _ => sum.iter().map(|s| s / count as f32).collect(),
+            };
+        }
+    }
+    centroids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `amount` distinct embeddings of `dims` dimensions.
+    fn embeddings(amount: usize, dims: usize) -> Vec<Vec<f32>> {
+        (0..amount)
+            .map(|i| {
+                (0..dims)
+                    .map(|d| ((i * 7 + d * 3) % 11) as f32 + i as f32 / 10.)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn subspaces() {
+        let pq = ProductQuantizer::train(&embeddings(20, 10), 3, 0);
+        assert_eq!(pq.subspaces, [0..3, 3..6, 6..10]);
+        assert_eq!(pq.bytes(), 3);
+        assert!(pq.codebooks.iter().all(|codebook| codebook.len() == 20));
+    }
+
+    #[test]
+    fn lossless_with_a_centroid_per_embedding() {
+        // With less than 256 embeddings, every embedding becomes a centroid
+        let embs = embeddings(20, 8);
+        let pq = ProductQuantizer::train(&embs, 4, 1);
+        for emb in &embs {
+            let codes = pq.encode(emb);
+            assert_eq!(&pq.decode(&codes), emb);
+            assert_eq!(pq.adc(Distance::L2, emb, &codes), 0.);
+            assert_eq!(pq.sdc(Distance::L2, &codes, &codes), 0.);
+        }
+    }
+
+    #[test]
+    fn deterministic() {
+        let embs = embeddings(30, 6);
+        let pq1 = ProductQuantizer::train(&embs, 2, 42);
+        let pq2 = ProductQuantizer::train(&embs, 2, 42);
+        assert_eq!(pq1.codebooks, pq2.codebooks);
+    }
+
+    #[test]
+    fn kmeans_finds_clusters() {
+        let data: [&[f32]; 6] = [
+            &[0., 0.],
+            &[0., 1.],
+            &[1., 0.],
+            &[10., 10.],
+            &[10., 11.],
+            &[11., 10.],
+        ];
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut centroids = kmeans(&data, 2, &mut rng);
+        centroids.sort_by(|a, b| a[0].total_cmp(&b[0]));
+        let expected = [[1. / 3., 1. / 3.], [31. / 3., 31. / 3.]];
+        for (centroid, expected) in centroids.iter().zip(expected) {
+            assert!(Distance::L2.calc(centroid, &expected) < 1e-9);
+        }
+    }
+}
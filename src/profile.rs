@@ -0,0 +1,224 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+use serde::{Deserialize, Serialize};
+
+use crate::distance::Distance;
+
+/// Amount of dimensions of a full embedding
+pub const EMBEDDING_SIZE: usize = 512;
+
+#[derive(Debug)]
+/// All possible errors when loading or storing a `ReductionProfile`.
+pub enum Error {
+    /// Profile file could not be read or written
+    IoError(std::io::Error),
+
+    /// Profile file could not be (de-)serialized
+    SerializeError(serde_json::Error),
+
+    /// Amount of bits is not within 1..=32
+    InvalidBits(u8),
+
+    /// Index of a kept dimension is not smaller than `EMBEDDING_SIZE`
+    InvalidIndex(usize),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Self::SerializeError(value)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+/// Type used to store a single dimension of a reduced embedding.
+pub enum DType {
+    I8,
+    I16,
+    I32,
+
+    /// No quantization, the selected dimensions are stored as they are
+    F32,
+}
+
+impl DType {
+    /// Amount of bits needed to store a single dimension.
+    pub fn bits(&self) -> u8 {
+        match self {
+            DType::I8 => 8,
+            DType::I16 => 16,
+            DType::I32 => 32,
+            DType::F32 => 32,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Describes how a full embedding is reduced: which dimensions are kept and how they are
+/// quantized.
+pub struct ReductionProfile {
+    /// Indices of the dimensions that are kept
+    pub indices: Vec<usize>,
+
+    /// Factor each kept dimension is multiplied with before quantization
+    pub scale: f32,
+
+    /// Amount of bits used per quantized dimension. Has to be <= the bits of `dtype`
+    pub bits: u8,
+
+    /// Type used to store each kept dimension
+    pub dtype: DType,
+
+    /// Name of the dataset the indices have been selected on
+    pub dataset: String,
+
+    /// Metric the indices have been selected for
+    pub metric: Distance,
+}
+
+impl Default for ReductionProfile {
+    /// Profile presented in our publication: 70 dimensions, quantized to `i8` with a scale of 70.
+    fn default() -> Self {
+        Self {
+            indices: vec![
+                7, 9, 11, 21, 23, 30, 33, 35, 60, 61, 68, 84, 87, 92, 100, 120, 133, 134, 136, 156,
+                163, 165, 167, 172, 180, 193, 202, 208, 209, 210, 211, 220, 241, 249, 262, 264,
+                265, 268, 276, 279, 280, 281, 283, 294, 308, 322, 324, 325, 327, 338, 354, 360,
+                364, 366, 371, 382, 408, 420, 421, 427, 433, 458, 464, 469, 470, 478, 479, 485,
+                488, 490,
+            ],
+            scale: 70.,
+            bits: 8,
+            dtype: DType::I8,
+            dataset: "lfw".into(),
+            metric: Distance::L2,
+        }
+    }
+}
+
+impl ReductionProfile {
+    /// Loads a profile from the JSON file `path`.
+    ///
+    /// # Errors
+    /// - `Error::IoError` returned, if `path` can't be read
+    /// - `Error::SerializeError` returned, if `path` is not a valid profile
+    /// - `Error::InvalidBits` or `Error::InvalidIndex` returned, if the profile can't be applied
+    ///   (see `validate`)
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let data = std::fs::read_to_string(path)?;
+        let profile: Self = serde_json::from_str(&data)?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    /// Checks if the profile can be applied to full embeddings.
+    ///
+    /// # Errors
+    /// - `Error::InvalidBits` returned, if `bits` is not within 1..=32
+    /// - `Error::InvalidIndex` returned, if any of `indices` is >= `EMBEDDING_SIZE`
+    pub fn validate(&self) -> Result<(), Error> {
+        if !(1..=32).contains(&self.bits) {
+            return Err(Error::InvalidBits(self.bits));
+        }
+        match self.indices.iter().find(|&&i| i >= EMBEDDING_SIZE) {
+            Some(&i) => Err(Error::InvalidIndex(i)),
+            None => Ok(()),
+        }
+    }
+
+    /// Stores the profile as JSON file `path`. Existing files are overwritten.
+    ///
+    /// # Errors
+    /// - `Error::IoError` returned, if `path` can't be written
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Returns only the dimensions of `emb` listed in `indices`.
+    pub fn select(&self, emb: &[f32]) -> Vec<f32> {
+        self.indices.iter().map(|&i| emb[i]).collect()
+    }
+
+    /// Quantizes the (already selected) dimensions of `emb`. The values are truncated and
+    /// saturated the same way as `as` casts, but kept as `f32` to be usable by all metrics. At
+    /// least one bit (the sign) is used.
+    pub fn quantize(&self, emb: Vec<f32>) -> Vec<f32> {
+        if self.dtype == DType::F32 {
+            return emb;
+        }
+
+        let bits = self.bits.clamp(1, self.dtype.bits());
+        let max = ((1_i64 << (bits - 1)) - 1) as f32;
+        let min = -max - 1.;
+        emb.iter()
+            .map(|x| (x * self.scale).trunc().clamp(min, max))
+            .collect()
+    }
+
+    /// Selects and quantizes `emb`.
+    pub fn apply(&self, emb: &[f32]) -> Vec<f32> {
+        self.quantize(self.select(emb))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate() {
+        assert!(ReductionProfile::default().validate().is_ok());
+
+        let mut profile = ReductionProfile {
+            bits: 0,
+            ..Default::default()
+        };
+        assert!(matches!(profile.validate(), Err(Error::InvalidBits(0))));
+        profile.bits = 33;
+        assert!(matches!(profile.validate(), Err(Error::InvalidBits(33))));
+
+        profile.bits = 32;
+        profile.indices = vec![0, EMBEDDING_SIZE - 1, EMBEDDING_SIZE];
+        assert!(matches!(
+            profile.validate(),
+            Err(Error::InvalidIndex(EMBEDDING_SIZE))
+        ));
+    }
+
+    #[test]
+    fn quantize() {
+        let mut profile = ReductionProfile {
+            scale: 10.,
+            ..Default::default()
+        };
+        let emb = vec![0.05, -0.05, 1.5, -20., 20.];
+        assert_eq!(profile.quantize(emb.clone()), [0., 0., 15., -128., 127.]);
+
+        profile.bits = 1;
+        assert_eq!(profile.quantize(emb.clone()), [0., 0., 0., -1., 0.]);
+        profile.bits = 0;
+        assert_eq!(profile.quantize(emb), [0., 0., 0., -1., 0.]);
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+//! Data-independent random projections, as baseline for selecting random dimensions.
+
+use std::str::FromStr;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Distribution of the entries of a `RandomProjection`.
+pub enum Projection {
+    /// Standard normal entries
+    Gaussian,
+
+    /// Sparse entries as proposed by Achlioptas: `sqrt(3) * {1, 0, -1}` with probabilities
+    /// `{1/6, 2/3, 1/6}`
+    Achlioptas,
+
+    /// Very sparse entries as proposed by Li et al.: `sqrt(s) * {1, 0, -1}` with probabilities
+    /// `{1/(2s), 1 - 1/s, 1/(2s)}` and `s = sqrt(dimensions)`
+    VerySparse,
+}
+
+impl Projection {
+    pub const ALL: [Projection; 3] = [
+        Projection::Gaussian,
+        Projection::Achlioptas,
+        Projection::VerySparse,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Projection::Gaussian => "gaussian",
+            Projection::Achlioptas => "achlioptas",
+            Projection::VerySparse => "very-sparse",
+        }
+    }
+
+    /// Draws a single entry of a projection of `dims` dimensions.
+    fn sample(&self, rng: &mut StdRng, dims: usize) -> f32 {
+        let sparse = |rng: &mut StdRng, s: f32| {
+            let x: f32 = rng.gen();
+            if x < 1. / (2. * s) {
+                s.sqrt()
+            } else if x < 1. / s {
+                -s.sqrt()
+            } else {
+                0.
+            }
+        };
+        match self {
+            // Box-Muller transform
+            Projection::Gaussian => {
+                let u1: f32 = 1. - rng.gen::<f32>();
+                let u2: f32 = rng.gen();
+                (-2. * u1.ln()).sqrt() * (2. * std::f32::consts::PI * u2).cos()
+            }
+            Projection::Achlioptas => sparse(rng, 3.),
+            Projection::VerySparse => sparse(rng, (dims as f32).sqrt()),
+        }
+    }
+}
+
+impl FromStr for Projection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Projection::ALL
+            .into_iter()
+            .find(|projection| projection.name() == s)
+            .ok_or(format!(
+                "Unknown projection {s}, possible values: gaussian, achlioptas, very-sparse"
+            ))
+    }
+}
+
+/// Random projection matrix, the same seed always results in the same matrix.
+///
+/// The entries have unit variance, so the projection has to be scaled by `1/sqrt(amount)` (see
+/// `RandomProjection::scale`) to preserve distances in expectation.
+pub struct RandomProjection {
+    pub rows: Vec<Vec<f32>>,
+}
+
+impl RandomProjection {
+    /// Draws a matrix projecting embeddings of `dims` dimensions to `amount` dimensions.
+    pub fn new(projection: Projection, dims: usize, amount: usize, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let rows = (0..amount)
+            .map(|_| {
+                (0..dims)
+                    .map(|_| projection.sample(&mut rng, dims))
+                    .collect()
+            })
+            .collect();
+        Self { rows }
+    }
+
+    /// Projects `emb` using the first `amount` rows, without scaling.
+    pub fn project(&self, emb: &[f32], amount: usize) -> Vec<f32> {
+        self.rows[..amount]
+            .iter()
+            .map(|row| row.iter().zip(emb).map(|(a, b)| a * b).sum())
+            .collect()
+    }
+
+    /// Factor an embedding projected using `amount` rows has to be scaled with.
+    pub fn scale(amount: usize) -> f32 {
+        1. / (amount as f32).sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preserves_norm_in_expectation() {
+        let emb: Vec<f32> = (0..64)
+            .map(|i| if i % 2 == 0 { 0.125 } else { -0.125 })
+            .collect();
+        for projection in Projection::ALL {
+            let matrix = RandomProjection::new(projection, 64, 2000, 0);
+            let scale = RandomProjection::scale(2000);
+            let norm: f32 = matrix
+                .project(&emb, 2000)
+                .iter()
+                .map(|x| (x * scale).powi(2))
+                .sum();
+            assert!((norm - 1.).abs() < 0.1, "{}: {norm}", projection.name());
+        }
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+use serde::Serialize;
+
+use crate::misc::ConfusionMatrix;
+
+#[derive(Serialize, Debug, Clone)]
+/// Single point of a ROC curve. Pairs with a distance <= `threshold` are accepted.
+pub struct RocPoint<T> {
+    pub threshold: T,
+
+    /// true-positive rate (same person accepted)
+    pub tpr: f32,
+
+    /// false-positive rate (different people accepted)
+    pub fpr: f32,
+}
+
+#[derive(Serialize, Debug)]
+/// Receiver operating characteristic over all possible thresholds.
+pub struct Roc<T> {
+    /// Curve sorted by ascending threshold, i.e. ascending `tpr` and `fpr`
+    pub points: Vec<RocPoint<T>>,
+
+    /// Area under the curve
+    pub auc: f32,
+
+    /// Equal error rate, i.e. the rate where false-positive and false-negative rate are (nearly)
+    /// the same
+    pub eer: f32,
+
+    /// Threshold used to reach `eer`
+    pub eer_threshold: T,
+}
+
+impl<T: PartialOrd + Copy + std::fmt::Display> Roc<T> {
+    /// Sweeps all thresholds in a single pass over the sorted distances. If `same` or `diff` is
+    /// empty, the corresponding rates (and therefore `auc` and `eer`) are NaN.
+    ///
+    /// # Panics
+    /// - Panics if both `same` and `diff` are empty or a distance can't be compared (e.g. NaN)
+    pub fn new(same: &[T], diff: &[T]) -> Self {
+        Self::with_rejected(same, diff, 0, 0)
+    }
+
+    /// Same as `new`, but additionally `rejected_same` and `rejected_diff` pairs are rejected at
+    /// every threshold. Rejected pairs count towards the amount of genuine and impostor pairs, so
+    /// the rates are only NaN if there are neither (rejected) genuine nor impostor pairs.
+    ///
+    /// # Panics
+    /// - Panics if both `same` and `diff` are empty or a distance can't be compared (e.g. NaN)
+    pub fn with_rejected(
+        same: &[T],
+        diff: &[T],
+        rejected_same: usize,
+        rejected_diff: usize,
+    ) -> Self {
+        let amount_same = (same.len() + rejected_same) as f32;
+        let amount_diff = (diff.len() + rejected_diff) as f32;
+        let points: Vec<RocPoint<T>> = ConfusionMatrix::sweep(same, diff)
+            .into_iter()
+            .map(|(threshold, conf)| RocPoint {
+                threshold,
+                tpr: conf.tp as f32 / amount_same,
+                fpr: conf.fp as f32 / amount_diff,
+            })
+            .collect();
+
+        let mut auc = 0.;
+        let (mut prev_tpr, mut prev_fpr) = (0., 0.);
+        for p in &points {
+            auc += (p.fpr - prev_fpr) * (p.tpr + prev_tpr) / 2.;
+            (prev_tpr, prev_fpr) = (p.tpr, p.fpr);
+        }
+        // Rejected impostors are never accepted, so the curve ends before a false-positive rate
+        // of 1. Accepting them as well wouldn't change the true-positive rate.
+        auc += (1. - prev_fpr) * prev_tpr;
+
+        let eer_point = points
+            .iter()
+            .min_by(|a, b| {
+                ((1. - a.tpr) - a.fpr)
+                    .abs()
+                    .total_cmp(&((1. - b.tpr) - b.fpr).abs())
+            })
+            .unwrap();
+
+        Self {
+            eer: ((1. - eer_point.tpr) + eer_point.fpr) / 2.,
+            eer_threshold: eer_point.threshold,
+            auc,
+            points,
+        }
+    }
+
+    /// Returns the point with the highest true-positive rate whose false-positive rate does not
+    /// exceed `far`, i.e. the true accept rate at the given false accept rate. Returns `None` if
+    /// every threshold exceeds `far`.
+    pub fn at_far(&self, far: f32) -> Option<&RocPoint<T>> {
+        self.points.iter().take_while(|p| p.fpr <= far).last()
+    }
+
+    /// Returns the curve as CSV, one `threshold;tpr;fpr` line per point (without header).
+    pub fn to_csv(&self) -> String {
+        self.points
+            .iter()
+            .map(|p| format!("{};{};{}\n", p.threshold, p.tpr, p.fpr))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separable() {
+        let roc = Roc::new(&[0.1, 0.2], &[0.8, 0.9]);
+        assert_eq!(roc.auc, 1.);
+        assert_eq!(roc.eer, 0.);
+        assert_eq!(roc.eer_threshold, 0.2);
+    }
+
+    #[test]
+    fn overlapping() {
+        let roc = Roc::new(&[0.1, 0.2, 0.3], &[0.1, 0.2, 0.3]);
+        assert!((roc.auc - 0.5).abs() < 1e-6);
+        assert!((roc.eer - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn rejected_pairs_lower_tpr() {
+        let roc = Roc::with_rejected(&[0.1], &[0.9], 1, 0);
+        assert_eq!(roc.points[0].tpr, 0.5);
+        assert_eq!(roc.auc, 0.5);
+    }
+
+    #[test]
+    fn rejected_impostors_keep_auc() {
+        let roc = Roc::with_rejected(&[0.1], &[0.9], 0, 1);
+        assert_eq!(roc.points.last().unwrap().fpr, 0.5);
+        assert_eq!(roc.auc, 1.);
+        assert_eq!(roc.eer, 0.);
+
+        let roc = Roc::with_rejected(&[0.1, 0.2, 0.3], &[0.1, 0.2, 0.3], 0, 3);
+        assert!((roc.auc - 0.75).abs() < 1e-6);
+    }
+
+    #[test]
+    fn empty_diff_is_nan() {
+        let roc = Roc::new(&[0.1, 0.2], &[]);
+        assert!(roc.auc.is_nan());
+        assert!(roc.points.iter().all(|p| p.fpr.is_nan()));
+    }
+
+    #[test]
+    fn at_far() {
+        let roc = Roc::new(&[0.1, 0.3, 0.5], &[0.2, 0.4, 0.6, 0.8]);
+        assert_eq!(roc.at_far(0.).unwrap().threshold, 0.1);
+        let point = roc.at_far(0.25).unwrap();
+        assert_eq!(point.threshold, 0.3);
+        assert!((point.tpr - 2. / 3.).abs() < 1e-6);
+        assert_eq!(roc.at_far(1.).unwrap().threshold, 0.8);
+
+        let roc = Roc::new(&[0.5], &[0.1]);
+        assert!(roc.at_far(0.).is_none());
+    }
+}
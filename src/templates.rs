@@ -0,0 +1,405 @@
+// Copyright (C) 2024  Johannes Kepler University Linz, Institute of Networks and Security
+// Copyright (C) 2024  CDL Digidow <https://www.digidow.eu/>
+//
+// Licensed under the EUPL, Version 1.2 or – as soon they will be approved by
+// the European Commission - subsequent versions of the EUPL (the "Licence").
+// You may not use this work except in compliance with the Licence.
+//
+// You should have received a copy of the European Union Public License along
+// with this program.  If not, you may obtain a copy of the Licence at:
+// <https://joinup.ec.europa.eu/software/page/eupl>
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the Licence is distributed on an "AS IS" basis,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the Licence for the specific language governing permissions and
+// limitations under the Licence.
+
+//! Template-based (IJB-style) verification, where each side of a pair is a set of images.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::arcface::Recognition;
+use crate::distance::Distance;
+use crate::eval::{Evaluation, Folds, Protocol, Result};
+use crate::failure::MissingFace;
+use crate::{Dataset, IsSamePerson, AMOUNT_FOLDS};
+
+#[derive(Debug)]
+/// All possible Templates Errors
+pub enum Error {
+    /// Templates or pairs file could not be read
+    CsvError(csv::Error),
+
+    /// Line of the templates or pairs file could not be parsed
+    InvalidLine(String),
+
+    /// Pairs file references a template not contained in the templates file
+    UnknownTemplate(String),
+}
+
+impl From<csv::Error> for Error {
+    fn from(value: csv::Error) -> Self {
+        Self::CsvError(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Defines how the images of two templates are combined to a single score.
+pub enum Fusion {
+    /// The embeddings of each template are averaged
+    Mean,
+
+    /// The embeddings of each template are averaged, weighted by the quality of the images
+    QualityMean,
+
+    /// Score of the most similar pair of images, i.e. the smallest distance
+    MaxScore,
+
+    /// Score of the least similar pair of images, i.e. the largest distance
+    MinScore,
+}
+
+impl Fusion {
+    pub const ALL: [Fusion; 4] = [
+        Fusion::Mean,
+        Fusion::QualityMean,
+        Fusion::MaxScore,
+        Fusion::MinScore,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Fusion::Mean => "mean",
+            Fusion::QualityMean => "quality-mean",
+            Fusion::MaxScore => "max-score",
+            Fusion::MinScore => "min-score",
+        }
+    }
+
+    /// Distance between two templates, each consisting of `(embedding, quality)` per image.
+    ///
+    /// # Panics
+    /// - Panics if a template is empty
+    pub fn calc(&self, metric: Distance, t1: &[(Vec<f32>, f32)], t2: &[(Vec<f32>, f32)]) -> f32 {
+        let pairwise = || {
+            t1.iter()
+                .flat_map(|(emb1, _)| t2.iter().map(|(emb2, _)| metric.calc(emb1, emb2)))
+        };
+        match self {
+            Fusion::Mean => metric.calc(&mean(t1, false), &mean(t2, false)),
+            Fusion::QualityMean => metric.calc(&mean(t1, true), &mean(t2, true)),
+            Fusion::MaxScore => pairwise().fold(f32::INFINITY, f32::min),
+            Fusion::MinScore => pairwise().fold(f32::NEG_INFINITY, f32::max),
+        }
+    }
+}
+
+impl FromStr for Fusion {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Fusion::ALL
+            .into_iter()
+            .find(|fusion| fusion.name() == s)
+            .ok_or(format!(
+                "Unknown fusion {s}, possible values: mean, quality-mean, max-score, min-score"
+            ))
+    }
+}
+
+/// Mean of all embeddings of `template`, weighted by their quality if `weighted` is set.
+fn mean(template: &[(Vec<f32>, f32)], weighted: bool) -> Vec<f32> {
+    let mut ret = vec![0.; template[0].0.len()];
+    let mut sum_weights = 0.;
+    for (emb, quality) in template {
+        let weight = if weighted { *quality } else { 1. };
+        for (x, y) in ret.iter_mut().zip(emb) {
+            *x += weight * y;
+        }
+        sum_weights += weight;
+    }
+    if sum_weights > 0. {
+        ret.iter_mut().for_each(|x| *x /= sum_weights);
+    }
+    ret
+}
+
+/// Single template: a set of images of the same subject.
+pub struct Template {
+    pub subject: String,
+
+    /// Path and quality of each image
+    pub images: Vec<(String, f32)>,
+}
+
+/// Dataset consisting of templates and pairs of templates.
+///
+/// The templates file consists of `template_id<TAB>subject_id<TAB>path[<TAB>quality]` lines, one
+/// per image, with paths relative to a base directory. The quality defaults to 1. The pairs file
+/// consists of `template_id1<TAB>template_id2` lines, pairs of templates with the same subject are
+/// genuine. For the 10-fold protocol, the pairs are split into 10 consecutive blocks.
+///
+/// As `Dataset`, all pairs of images of each template pair are used (i.e. without fusion).
+pub struct Templates {
+    pub templates: HashMap<String, Template>,
+    pub pairs: Vec<(String, String)>,
+
+    /// Subject of each image
+    subjects: HashMap<String, String>,
+
+    /// Fold of each pair of images (see `Dataset::pairs`), i.e. the fold of its template pair
+    image_pair_folds: Vec<usize>,
+
+    /// Used to keep the caches of different templates files apart
+    name: String,
+}
+
+fn reader(path: &str) -> std::result::Result<csv::Reader<std::fs::File>, csv::Error> {
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(b'\t')
+        .from_path(path)
+}
+
+impl Templates {
+    pub fn new(
+        templates_file: &str,
+        pairs_file: &str,
+        basepath: String,
+    ) -> std::result::Result<Self, Error> {
+        let mut templates: HashMap<String, Template> = HashMap::new();
+        let mut subjects = HashMap::new();
+        for record in reader(templates_file)?.records() {
+            let record = record?;
+            let invalid = || Error::InvalidLine(record.iter().collect::<Vec<_>>().join("\t"));
+            let quality = match record.get(3) {
+                Some(quality) => quality.parse().map_err(|_| invalid())?,
+                None => 1.,
+            };
+            let (Some(id), Some(subject), Some(path)) =
+                (record.get(0), record.get(1), record.get(2))
+            else {
+                return Err(invalid());
+            };
+
+            let path = format!("{basepath}/{path}");
+            subjects.insert(path.clone(), subject.to_string());
+            templates
+                .entry(id.into())
+                .or_insert_with(|| Template {
+                    subject: subject.into(),
+                    images: Vec::new(),
+                })
+                .images
+                .push((path, quality));
+        }
+
+        let mut pairs = Vec::new();
+        for record in reader(pairs_file)?.records() {
+            let record = record?;
+            let (Some(id1), Some(id2)) = (record.get(0), record.get(1)) else {
+                return Err(Error::InvalidLine(
+                    record.iter().collect::<Vec<_>>().join("\t"),
+                ));
+            };
+            for id in [id1, id2] {
+                if !templates.contains_key(id) {
+                    return Err(Error::UnknownTemplate(id.into()));
+                }
+            }
+            pairs.push((id1.to_string(), id2.to_string()));
+        }
+
+        let image_pair_folds = pairs
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, (id1, id2))| {
+                let amount = templates[id1].images.len() * templates[id2].images.len();
+                vec![idx * AMOUNT_FOLDS / pairs.len(); amount]
+            })
+            .collect();
+        let name = Path::new(templates_file)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Ok(Self {
+            templates,
+            pairs,
+            subjects,
+            image_pair_folds,
+            name,
+        })
+    }
+
+    fn same_person(&self, id1: &str, id2: &str) -> IsSamePerson {
+        self.templates[id1].subject == self.templates[id2].subject
+    }
+
+    /// Embeddings and qualities of the images of all templates. Images without embedding (see
+    /// `Recognition::embedding`) are left out.
+    pub fn embeddings(&self, rec: &mut Recognition) -> HashMap<String, Vec<(Vec<f32>, f32)>> {
+        self.templates
+            .iter()
+            .map(|(id, template)| {
+                let embs = template
+                    .images
+                    .iter()
+                    .filter_map(|(path, quality)| Some((rec.embedding(path)?, *quality)))
+                    .collect();
+                (id.clone(), embs)
+            })
+            .collect()
+    }
+
+    /// Evaluates all template pairs using `fusion`, after reducing each embedding of
+    /// `embeddings` (see `embeddings`) using `reduce`. Pairs with an empty template are handled
+    /// according to `missing`, i.e. rejected for `MissingFace::FalseReject` and skipped
+    /// otherwise.
+    pub fn evaluate<'a, F: Fn(&[f32]) -> Vec<f32>>(
+        &self,
+        embeddings: &HashMap<String, Vec<(Vec<f32>, f32)>>,
+        eval: &'a Evaluation,
+        missing: MissingFace,
+        fusion: Fusion,
+        reduce: F,
+    ) -> Folds<'a, f32> {
+        let reduced: HashMap<&String, Vec<(Vec<f32>, f32)>> = embeddings
+            .iter()
+            .map(|(id, embs)| {
+                let embs = embs.iter().map(|(emb, q)| (reduce(emb), *q)).collect();
+                (id, embs)
+            })
+            .collect();
+
+        let amount_folds = match eval.protocol {
+            Protocol::Full => 1,
+            Protocol::TenFold => AMOUNT_FOLDS,
+        };
+        let mut folds: Vec<Result<f32>> = (0..amount_folds).map(|_| Result::new()).collect();
+        for (idx, (id1, id2)) in self.pairs.iter().enumerate() {
+            let result = &mut folds[idx * amount_folds / self.pairs.len()];
+            let same_person = self.same_person(id1, id2);
+            let (t1, t2) = (&reduced[id1], &reduced[id2]);
+            if t1.is_empty() || t2.is_empty() {
+                if missing == MissingFace::FalseReject {
+                    result.reject(same_person);
+                }
+                continue;
+            }
+
+            let dist = fusion.calc(eval.metric, t1, t2);
+            match same_person {
+                true => result.add_same(dist),
+                false => result.add_diff(dist),
+            }
+        }
+
+        Folds { eval, folds }
+    }
+}
+
+impl Dataset for Templates {
+    fn pairs(&self) -> Vec<(bool, String, String)> {
+        let mut ret = Vec::new();
+        for (id1, id2) in &self.pairs {
+            let same_person = self.same_person(id1, id2);
+            for (path1, _) in &self.templates[id1].images {
+                for (path2, _) in &self.templates[id2].images {
+                    ret.push((same_person, path1.clone(), path2.clone()));
+                }
+            }
+        }
+        ret
+    }
+
+    /// All pairs of images of a template pair belong to the same fold, the same one as in
+    /// `Templates::evaluate`.
+    fn fold(&self, idx: usize) -> usize {
+        self.image_pair_folds[idx]
+    }
+
+    /// All images of all templates, including the ones not used by any pair.
+    fn images(&self) -> BTreeSet<String> {
+        self.subjects.keys().cloned().collect()
+    }
+
+    fn identity(&self, path: &str) -> String {
+        self.subjects.get(path).cloned().unwrap_or_default()
+    }
+
+    fn templates(&self) -> Option<&Templates> {
+        Some(self)
+    }
+
+    fn name(&self) -> String {
+        format!("templates-{}", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fusion() {
+        let t1 = [(vec![0., 4.], 1.), (vec![2., 4.], 3.)];
+        let t2 = [(vec![5., 4.], 1.), (vec![9., 4.], 1.)];
+
+        // Means (1, 4) and (7, 4), quality-weighted (1.5, 4) and (7, 4); pairwise distances 5, 9, 3
+        // and 7
+        for (fusion, expected) in Fusion::ALL.into_iter().zip([6., 5.5, 3., 9.]) {
+            assert_eq!(fusion.calc(Distance::L1, &t1, &t2), expected);
+            assert_eq!(fusion.calc(Distance::L1, &t2, &t1), expected);
+        }
+        assert_eq!(Fusion::MaxScore.calc(Distance::L1, &t1[..1], &t2[..1]), 5.);
+    }
+
+    #[test]
+    fn image_pairs_share_fold_of_template_pair() {
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        let templates_file = dir.join(format!("reducedemb-templates-{id}.txt"));
+        let pairs_file = dir.join(format!("reducedemb-template-pairs-{id}.txt"));
+
+        // Template `t<n>` consists of n + 1 images, so the template pairs expand to different
+        // amounts of image pairs
+        let templates: String = (0..4)
+            .flat_map(|t| (0..=t).map(move |i| format!("t{t}\ts{}\tt{t}/{i}.jpg\n", t % 2)))
+            .collect();
+        let pairs: Vec<(usize, usize)> = (0..20).map(|i| (i % 4, (i / 4) % 4)).collect();
+        std::fs::write(&templates_file, templates).unwrap();
+        std::fs::write(
+            &pairs_file,
+            pairs
+                .iter()
+                .map(|(t1, t2)| format!("t{t1}\tt{t2}\n"))
+                .collect::<String>(),
+        )
+        .unwrap();
+
+        let data = Templates::new(
+            templates_file.to_str().unwrap(),
+            pairs_file.to_str().unwrap(),
+            String::new(),
+        )
+        .unwrap();
+        let expected: Vec<usize> = pairs
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, (t1, t2))| vec![idx * AMOUNT_FOLDS / pairs.len(); (t1 + 1) * (t2 + 1)])
+            .collect();
+        assert_eq!(data.pairs().len(), expected.len());
+        for (idx, fold) in expected.iter().enumerate() {
+            assert_eq!(data.fold(idx), *fold);
+        }
+
+        let _ = std::fs::remove_file(templates_file);
+        let _ = std::fs::remove_file(pairs_file);
+    }
+}